@@ -70,11 +70,90 @@ create table metric_docker_containers
     timestamp timestamp with time zone not null,
     name text,
     state text not null,
-    cpu_usage double precision not null,
-    memory_usage bigint not null,
-    memory_cache bigint not null,
-    network_tx double precision not null,
-    network_rx double precision not null
+    image text,
+    compose_project text,
+    compose_service text,
+    health text,
+    restart_count integer,
+    exit_code integer,
+    created_at timestamp with time zone,
+    started_at timestamp with time zone,
+    cpu_usage double precision,
+    cpu_throttled_periods double precision,
+    cpu_throttled_time double precision,
+    memory_usage bigint,
+    memory_cache bigint,
+    memory_limit bigint,
+    memory_percent double precision,
+    network_tx double precision,
+    network_rx double precision,
+    block_read double precision,
+    block_write double precision,
+    block_read_ops double precision,
+    block_write_ops double precision,
+    pids integer
+);
+
+create table metric_docker_container_transitions
+(
+    hostname text not null,
+    timestamp timestamp with time zone not null,
+    name text not null,
+    previous_state text not null,
+    state text not null,
+    exit_code integer not null
+);
+
+create table metric_docker_container_networks
+(
+    hostname text not null,
+    timestamp timestamp with time zone not null,
+    name text not null,
+    interface text not null,
+    network text,
+    rx_bytes double precision not null,
+    rx_packets double precision not null,
+    rx_errors double precision not null,
+    rx_dropped double precision not null,
+    tx_bytes double precision not null,
+    tx_packets double precision not null,
+    tx_errors double precision not null,
+    tx_dropped double precision not null
+);
+
+create table metric_docker_events
+(
+    hostname text not null,
+    timestamp timestamp with time zone not null,
+    container_id text not null,
+    name text,
+    action text not null,
+    exit_code integer,
+    attributes text not null
+);
+
+create table metric_docker_disk_usage
+(
+    hostname text not null,
+    timestamp timestamp with time zone not null,
+    images bigint not null,
+    images_size bigint not null,
+    images_reclaimable bigint not null,
+    containers_size bigint not null,
+    volumes bigint not null,
+    volumes_size bigint not null,
+    volumes_reclaimable bigint not null,
+    build_cache_size bigint not null,
+    build_cache_reclaimable bigint not null
+);
+
+create table metric_docker_volumes
+(
+    hostname text not null,
+    timestamp timestamp with time zone not null,
+    name text not null,
+    size bigint,
+    ref_count integer
 );
 
 create table metric_nginx
@@ -84,22 +163,368 @@ create table metric_nginx
     handled_requests integer not null
 );
 
+create table metric_redis
+(
+    hostname text not null,
+    timestamp timestamp with time zone not null,
+    connected_clients integer not null,
+    blocked_clients integer not null,
+    used_memory bigint not null,
+    used_memory_peak bigint not null,
+    used_memory_rss bigint not null,
+    mem_fragmentation_ratio double precision not null,
+    ops double precision not null,
+    keyspace_hits double precision not null,
+    keyspace_misses double precision not null,
+    hit_ratio double precision,
+    evicted_keys double precision not null,
+    expired_keys double precision not null,
+    role text not null,
+    repl_offset bigint not null,
+    master_link_up boolean,
+    master_last_io_seconds_ago bigint
+);
+
+create table metric_redis_keyspace
+(
+    hostname text not null,
+    timestamp timestamp with time zone not null,
+    db text not null,
+    keys bigint not null,
+    expires bigint not null
+);
+
+create table metric_redis_replicas
+(
+    hostname text not null,
+    timestamp timestamp with time zone not null,
+    address text not null,
+    state text not null,
+    offset_lag bigint not null,
+    lag bigint not null
+);
+
+create table metric_mysql
+(
+    hostname text not null,
+    timestamp timestamp with time zone not null,
+    queries double precision not null,
+    slow_queries double precision not null,
+    connections integer not null,
+    max_connections integer not null,
+    connections_percent double precision not null,
+    threads_running integer not null,
+    buffer_pool_hit_ratio double precision,
+    rows_read double precision not null,
+    rows_inserted double precision not null,
+    rows_updated double precision not null,
+    rows_deleted double precision not null,
+    replication_io_running boolean,
+    replication_sql_running boolean,
+    replication_lag bigint
+);
+
 create table metric_postgres_database
 (
-    hostname text,
-    timestamp timestamp with time zone,
-    returned integer,
-    fetched integer,
-    inserted integer,
-    updated integer,
-    deleted integer
+    hostname text not null,
+    timestamp timestamp with time zone not null,
+    target text not null,
+    database text not null,
+    xact_commit double precision not null,
+    xact_rollback double precision not null,
+    blks_read double precision not null,
+    blks_hit double precision not null,
+    cache_hit_ratio double precision,
+    returned double precision not null,
+    fetched double precision not null,
+    inserted double precision not null,
+    updated double precision not null,
+    deleted double precision not null,
+    conflicts double precision not null,
+    temp_files double precision not null,
+    temp_bytes double precision not null,
+    deadlocks double precision not null,
+    blk_read_time double precision not null,
+    blk_write_time double precision not null
+);
+
+create table metric_postgres_activity
+(
+    hostname text not null,
+    timestamp timestamp with time zone not null,
+    target text not null,
+    connections integer not null,
+    max_connections integer not null,
+    connections_percent double precision not null,
+    longest_query double precision not null,
+    longest_transaction double precision not null,
+    waiting_on_locks integer not null
+);
+
+create table metric_postgres_connections
+(
+    hostname text not null,
+    timestamp timestamp with time zone not null,
+    target text not null,
+    database text not null,
+    user_name text not null,
+    application text not null,
+    state text not null,
+    connections integer not null
+);
+
+create table metric_postgres_replication
+(
+    hostname text not null,
+    timestamp timestamp with time zone not null,
+    target text not null,
+    in_recovery boolean not null,
+    wal_rate double precision,
+    replay_lag double precision,
+    replay_lag_bytes bigint,
+    receiver_status text
+);
+
+create table metric_postgres_replicas
+(
+    hostname text not null,
+    timestamp timestamp with time zone not null,
+    target text not null,
+    application_name text not null,
+    client_addr text not null,
+    state text not null,
+    sync_state text not null,
+    write_lag_bytes bigint not null,
+    flush_lag_bytes bigint not null,
+    replay_lag_bytes bigint not null,
+    write_lag double precision not null,
+    flush_lag double precision not null,
+    replay_lag double precision not null
+);
+
+create table metric_postgres_replication_slots
+(
+    hostname text not null,
+    timestamp timestamp with time zone not null,
+    target text not null,
+    slot_name text not null,
+    slot_type text not null,
+    active boolean not null,
+    retained_wal bigint not null
+);
+
+create table metric_postgres_statements
+(
+    hostname text not null,
+    timestamp timestamp with time zone not null,
+    target text not null,
+    queryid bigint not null,
+    database text not null,
+    user_name text not null,
+    calls bigint not null,
+    total_time double precision not null,
+    mean_time double precision not null,
+    rows bigint not null,
+    shared_blks_read bigint not null,
+    shared_blks_hit bigint not null
+);
+
+create table postgres_statements
+(
+    target text not null,
+    queryid bigint not null,
+    query text not null,
+    last_seen timestamp with time zone not null,
+    primary key (target, queryid)
+);
+
+create table metric_postgres_bgwriter
+(
+    hostname text not null,
+    timestamp timestamp with time zone not null,
+    target text not null,
+    checkpoints_timed double precision not null,
+    checkpoints_req double precision not null,
+    checkpoint_write_time double precision not null,
+    checkpoint_sync_time double precision not null,
+    buffers_checkpoint double precision not null,
+    buffers_clean double precision not null,
+    maxwritten_clean double precision not null,
+    buffers_backend double precision,
+    buffers_backend_fsync double precision,
+    buffers_alloc double precision not null
+);
+
+create table metric_postgres_locks
+(
+    hostname text not null,
+    timestamp timestamp with time zone not null,
+    target text not null,
+    mode text not null,
+    granted boolean not null,
+    count integer not null
+);
+
+create table metric_postgres_blocking
+(
+    hostname text not null,
+    timestamp timestamp with time zone not null,
+    target text not null,
+    database text not null,
+    blocked_pid integer not null,
+    blocking_pid integer not null,
+    duration double precision not null,
+    query text not null
+);
+
+create table metric_postgres_indexes
+(
+    hostname text not null,
+    timestamp timestamp with time zone not null,
+    target text not null,
+    schema text not null,
+    table_name text not null,
+    name text not null,
+    scans bigint not null,
+    size bigint not null,
+    is_unique boolean not null,
+    unused boolean not null,
+    duplicate boolean not null,
+    invalid boolean not null
 );
 
 create table metric_postgres_tables
 (
     hostname text not null,
     timestamp timestamp with time zone not null,
+    target text not null,
+    schema text not null,
     name text not null,
-    rows integer not null,
+    rows bigint not null,
+    seq_scan double precision not null,
+    seq_tup_read double precision not null,
+    idx_scan double precision not null,
+    live_tuples bigint not null,
+    dead_tuples bigint not null,
+    dead_tuple_ratio double precision not null,
+    last_vacuum_age double precision,
+    last_autovacuum_age double precision,
+    last_analyze_age double precision,
+    last_autoanalyze_age double precision,
+    table_bytes bigint not null,
+    index_bytes bigint not null,
+    toast_bytes bigint not null,
     total_bytes bigint not null
 );
+
+create table metric_conntrack
+(
+    hostname text not null,
+    timestamp timestamp with time zone not null,
+    count bigint not null,
+    max bigint not null,
+    usage double precision not null,
+    "drop" double precision not null,
+    insert_failed double precision not null,
+    early_drop double precision not null
+);
+
+create table metric_process
+(
+    hostname text not null,
+    timestamp timestamp with time zone not null,
+    pid integer not null,
+    name text not null,
+    cpu_usage double precision not null,
+    memory_rss bigint not null,
+    read double precision not null,
+    write double precision not null,
+    threads integer not null
+);
+
+create table metric_process_watch
+(
+    hostname text not null,
+    timestamp timestamp with time zone not null,
+    name text not null,
+    running boolean not null,
+    instances integer not null,
+    pid integer,
+    start_time timestamp with time zone
+);
+
+create table metric_process_restarts
+(
+    hostname text not null,
+    timestamp timestamp with time zone not null,
+    name text not null,
+    previous_pid integer,
+    previous_start_time timestamp with time zone,
+    pid integer not null,
+    start_time timestamp with time zone not null
+);
+
+create table hosts
+(
+    hostname text primary key,
+    kernel text not null,
+    os_release text,
+    cpu_model text,
+    cpu_cores integer not null,
+    memory_total bigint not null,
+    boot_time timestamp with time zone not null,
+    uptime double precision not null,
+    agent_version text not null,
+    last_seen timestamp with time zone not null
+);
+
+create table metric_kernel_limits
+(
+    hostname text not null,
+    timestamp timestamp with time zone not null,
+    files_allocated bigint not null,
+    files_max bigint not null,
+    inodes bigint not null,
+    inodes_free bigint not null,
+    pid_max bigint not null,
+    processes integer not null,
+    threads integer not null
+);
+
+create table metric_process_fds
+(
+    hostname text not null,
+    timestamp timestamp with time zone not null,
+    name text not null,
+    pid integer not null,
+    open_fds integer not null,
+    max_fds bigint
+);
+
+create table metric_cgroup_containers
+(
+    hostname text not null,
+    timestamp timestamp with time zone not null,
+    id text not null,
+    name text not null,
+    cpu_usage double precision not null,
+    cpu_throttled_periods double precision not null,
+    cpu_throttled_time double precision not null,
+    memory_usage bigint not null,
+    memory_limit bigint,
+    io_read double precision not null,
+    io_write double precision not null,
+    pids integer not null
+);
+
+create table metric_systemd_units
+(
+    hostname text not null,
+    timestamp timestamp with time zone not null,
+    unit text not null,
+    cpu_usage double precision not null,
+    memory_usage bigint not null,
+    io_read double precision not null,
+    io_write double precision not null,
+    tasks integer not null
+);
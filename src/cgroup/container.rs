@@ -0,0 +1,277 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use async_std::fs::read_dir;
+use chrono::{Utc, DateTime, Duration};
+use futures::future::{join_all, try_join_all};
+use futures::StreamExt;
+use async_trait::async_trait;
+use log::warn;
+use regex::Regex;
+use serde::Serialize;
+
+use crate::database::Database;
+use crate::config::get_max_metrics_age;
+use crate::cgroup::stat::{CgroupUsage, get_cgroup_root, is_cgroup_v2, read_v1_usage, read_v2_usage};
+use crate::docker::client::DockerClient;
+use crate::types::{Metric, MetricCollectionError, MetricSaveError, MetricCleanupError, MetricCollector, MetricEncodingError};
+
+// docker, containerd, cri-o and podman name container cgroups after the 64 character container id,
+// optionally with a runtime prefix and a systemd scope suffix.
+const CONTAINER_CGROUP_PATTERN: &str = r"^(?:docker-|cri-containerd-|crio-|libpod-)?([0-9a-f]{64})(?:\.scope)?$";
+
+#[derive(Debug, Clone)]
+pub struct InstantCgroupContainerMetric {
+    timestamp: DateTime<Utc>,
+    stat: Vec<InstantCgroupContainerMetricEntry>
+}
+
+#[derive(Debug, Clone)]
+pub struct InstantCgroupContainerMetricEntry {
+    id: String,
+    usage: CgroupUsage
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CgroupContainerMetric {
+    timestamp: DateTime<Utc>,
+    stat: Vec<CgroupContainerMetricEntry>
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CgroupContainerMetricEntry {
+    id: String,
+    name: String,
+
+    cpu_usage: f64, // cores
+    cpu_throttled_periods: f64,
+    cpu_throttled_time: f64, // seconds throttled per second
+
+    memory_usage: u64,
+    memory_limit: Option<u64>,
+
+    io_read: f64,
+    io_write: f64,
+
+    pids: u64
+}
+
+impl Metric for InstantCgroupContainerMetric {
+}
+
+pub struct CgroupContainerMetricCollector {
+    docker_client: Option<DockerClient>,
+    container_pattern: Regex,
+    names: HashMap<String, String>,
+    is_names_failing: bool,
+    previous: Option<InstantCgroupContainerMetric>,
+    metric: Option<CgroupContainerMetric>
+}
+
+impl CgroupContainerMetricCollector {
+
+    pub fn new(docker_client: Option<DockerClient>) -> Self {
+        CgroupContainerMetricCollector {
+            docker_client,
+            container_pattern: Regex::new(CONTAINER_CGROUP_PATTERN).expect("invalid container cgroup pattern"),
+            names: HashMap::new(),
+            is_names_failing: false,
+            previous: None,
+            metric: None
+        }
+    }
+
+    async fn collect_metric(&self) -> Result<Box<InstantCgroupContainerMetric>, MetricCollectionError> {
+        let timestamp = Utc::now();
+        let root = get_cgroup_root();
+        let is_v2 = is_cgroup_v2(&root);
+
+        // in v1 every controller has the same hierarchy, so it is enough to walk one of them
+        let walk_root = if is_v2 { root.clone() } else { root.join("memory") };
+        if !walk_root.exists() {
+            return Err(MetricCollectionError::NotConfigured {
+                description: format!("cgroup hierarchy not found at {}", walk_root.display())
+            });
+        }
+
+        let cgroups = find_container_cgroups(&self.container_pattern, &walk_root).await;
+
+        let stat = join_all(cgroups.into_iter().map(|(id, path)| {
+            let root = root.clone();
+            let walk_root = walk_root.clone();
+
+            async move {
+                let usage = if is_v2 {
+                    read_v2_usage(&path).await
+                } else {
+                    read_v1_usage(&root, path.strip_prefix(&walk_root).unwrap_or(&path)).await
+                };
+
+                usage.map(|usage| InstantCgroupContainerMetricEntry { id, usage })
+            }
+        })).await.into_iter()
+            .filter_map(|v| v.ok()) // containers may stop while we are reading them
+            .collect();
+
+        Ok(Box::new(InstantCgroupContainerMetric { timestamp, stat }))
+    }
+
+    async fn update_names(&mut self) {
+        let client = match &self.docker_client {
+            Some(v) => v,
+            None => return
+        };
+
+        // docker may start after the agent or not run at all (e.g. containerd or podman hosts), so this is retried
+        // every time, but only the first failure in a row is logged
+        match client.containers().await {
+            Ok(containers) => {
+                self.names = containers.into_iter()
+                    .filter_map(|v| Some((v.id, v.names.first()?.trim_start_matches('/').to_string())))
+                    .collect();
+                self.is_names_failing = false;
+            },
+            Err(err) => {
+                if !self.is_names_failing {
+                    warn!("failed to get container names, falling back to ids: {}", err);
+                }
+                self.is_names_failing = true;
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl MetricCollector for CgroupContainerMetricCollector {
+
+    fn key(&self) -> String {
+        "cgroup_containers".to_string()
+    }
+
+    async fn collect(&mut self) -> Result<(), MetricCollectionError> {
+        let metric = self.collect_metric().await?;
+        self.update_names().await;
+
+        if let Some(prev) = &self.previous {
+            self.metric = Some(cgroup_metric_from_stats(prev, &metric, &self.names));
+        }
+        self.previous = Some(*metric);
+
+        Ok(())
+    }
+
+    async fn save(&self, database: &Database, hostname: &str) -> Result<(), MetricSaveError> {
+        if let Some(metric) = &self.metric {
+            let timestamp = metric.timestamp.clone();
+
+            let futures = metric.clone().stat.into_iter()
+                .map(|entry| save_metric_entry(&database, hostname, &timestamp, entry));
+
+            try_join_all(futures).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn encode(&self) -> Result<String, MetricEncodingError> {
+        if let Some(metric) = &self.metric {
+            let v = serde_json::to_string(metric)?;
+            return Ok(v);
+        }
+
+        Err(MetricEncodingError::NoRecord)
+    }
+
+    async fn cleanup(&self, mut database: &Database) -> Result<(), MetricCleanupError> {
+        let min_timestamp = Utc::now() - get_max_metrics_age();
+
+        sqlx::query!("delete from metric_cgroup_containers where timestamp < $1 returning 1 as result", min_timestamp)
+            .fetch_one(&mut database).await?;
+
+        Ok(())
+    }
+}
+
+async fn find_container_cgroups(pattern: &Regex, root: &Path) -> Vec<(String, PathBuf)> {
+    let mut result = Vec::new();
+    let mut dirs = vec![root.to_path_buf()];
+
+    while let Some(dir) = dirs.pop() {
+        let mut entries = match read_dir(&dir).await {
+            Ok(v) => v,
+            Err(_) => continue // cgroups may be removed while we are walking them
+        };
+
+        while let Some(entry) = entries.next().await {
+            let entry = match entry {
+                Ok(v) => v,
+                Err(_) => continue
+            };
+
+            if !entry.file_type().await.map(|v| v.is_dir()).unwrap_or(false) {
+                continue;
+            }
+
+            let path: PathBuf = entry.path().into();
+            let container_id = entry.file_name().to_str()
+                .and_then(|name| pattern.captures(name))
+                .and_then(|captures| captures.get(1))
+                .map(|id| id.as_str().to_string());
+
+            match container_id {
+                // nested cgroups of a container (e.g. podman's "container" sub-cgroup) are already accounted for
+                Some(id) => result.push((id, path)),
+                None => dirs.push(path)
+            }
+        }
+    }
+
+    result
+}
+
+fn cgroup_metric_from_stats(first: &InstantCgroupContainerMetric, second: &InstantCgroupContainerMetric, names: &HashMap<String, String>) -> CgroupContainerMetric {
+    let time_diff = second.timestamp - first.timestamp;
+
+    let first_entries: HashMap<&String, &CgroupUsage> = first.stat.iter()
+        .map(|v| (&v.id, &v.usage))
+        .collect();
+
+    let stat: Vec<CgroupContainerMetricEntry> = second.stat.iter()
+        .filter_map(|v| first_entries.get(&v.id)
+            .map(|item| cgroup_metric_entry_from_two_stats(time_diff, &v.id, names, item, &v.usage))
+        )
+        .collect();
+
+    CgroupContainerMetric { stat, timestamp: second.timestamp }
+}
+
+fn cgroup_metric_entry_from_two_stats(time_diff: Duration, id: &str, names: &HashMap<String, String>, first: &CgroupUsage, second: &CgroupUsage) -> CgroupContainerMetricEntry {
+    let diff = time_diff.num_milliseconds() as f64 / 1000.0; // seconds
+
+    CgroupContainerMetricEntry {
+        id: id.to_string(),
+        name: names.get(id).cloned().unwrap_or_else(|| id[..12].to_string()),
+
+        cpu_usage: second.cpu_usage.saturating_sub(first.cpu_usage) as f64 / 1_000_000_000.0 / diff,
+        cpu_throttled_periods: second.cpu_throttled_periods.saturating_sub(first.cpu_throttled_periods) as f64 / diff,
+        cpu_throttled_time: second.cpu_throttled_time.saturating_sub(first.cpu_throttled_time) as f64 / 1_000_000_000.0 / diff,
+
+        memory_usage: second.memory_usage,
+        memory_limit: second.memory_limit,
+
+        io_read: second.io_read.saturating_sub(first.io_read) as f64 / diff,
+        io_write: second.io_write.saturating_sub(first.io_write) as f64 / diff,
+
+        pids: second.pids
+    }
+}
+
+async fn save_metric_entry(mut database: &Database, hostname: &str, timestamp: &DateTime<Utc>, entry: CgroupContainerMetricEntry) -> Result<(), MetricSaveError> {
+    sqlx::query!(
+        "insert into metric_cgroup_containers (hostname, timestamp, id, name, cpu_usage, cpu_throttled_periods, cpu_throttled_time, memory_usage, memory_limit, io_read, io_write, pids) values ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12) returning hostname",
+        hostname.to_string(), *timestamp, entry.id, entry.name, entry.cpu_usage, entry.cpu_throttled_periods, entry.cpu_throttled_time,
+        entry.memory_usage as i64, entry.memory_limit.map(|v| v as i64), entry.io_read, entry.io_write, entry.pids as i32
+    ).fetch_one(&mut database).await?;
+
+    Ok(())
+}
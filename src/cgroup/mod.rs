@@ -0,0 +1,3 @@
+pub mod container;
+pub mod stat;
+pub mod systemd;
@@ -0,0 +1,138 @@
+use std::env;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use async_std::fs::read_to_string;
+
+use crate::types::MetricCollectionError;
+
+const DEFAULT_CGROUP_ROOT: &str = "/sys/fs/cgroup";
+
+// cgroup v1 reports "no limit" as a page-aligned i64::MAX
+const CGROUP_V1_UNLIMITED: u64 = 1 << 62;
+
+#[derive(Debug, Clone, Default)]
+pub struct CgroupUsage {
+    pub cpu_usage: u64, // nanoseconds
+    pub cpu_throttled_periods: u64,
+    pub cpu_throttled_time: u64, // nanoseconds
+
+    pub memory_usage: u64,
+    pub memory_limit: Option<u64>,
+
+    pub io_read: u64,
+    pub io_write: u64,
+
+    pub pids: u64
+}
+
+pub fn get_cgroup_root() -> PathBuf {
+    PathBuf::from(env::var("CGROUP_ROOT").unwrap_or(DEFAULT_CGROUP_ROOT.to_string()))
+}
+
+// unified hierarchy has cgroup.controllers at its root, v1 has a directory per controller instead
+pub fn is_cgroup_v2(root: &Path) -> bool {
+    root.join("cgroup.controllers").exists()
+}
+
+// Reads usage of a cgroup in the unified hierarchy. Files of controllers which are not enabled
+// for the cgroup are missing, those are reported as zeros.
+pub async fn read_v2_usage(path: &Path) -> Result<CgroupUsage, MetricCollectionError> {
+    let cpu_stat = read_flat_keyed(&path.join("cpu.stat")).await?;
+    let (io_read, io_write) = read_v2_io(&path.join("io.stat")).await;
+
+    Ok(CgroupUsage {
+        cpu_usage: cpu_stat.get("usage_usec").cloned().unwrap_or(0) * 1000,
+        cpu_throttled_periods: cpu_stat.get("nr_throttled").cloned().unwrap_or(0),
+        cpu_throttled_time: cpu_stat.get("throttled_usec").cloned().unwrap_or(0) * 1000,
+
+        memory_usage: read_value(&path.join("memory.current")).await.unwrap_or(0),
+        memory_limit: read_value(&path.join("memory.max")).await.ok(), // "max" when unlimited
+
+        io_read,
+        io_write,
+
+        pids: read_value(&path.join("pids.current")).await.unwrap_or(0)
+    })
+}
+
+// Reads usage of a cgroup with the given path relative to each controller hierarchy.
+pub async fn read_v1_usage(root: &Path, relative_path: &Path) -> Result<CgroupUsage, MetricCollectionError> {
+    let controller_path = |controller: &str| root.join(controller).join(relative_path);
+
+    let cpu_stat = read_flat_keyed(&controller_path("cpu").join("cpu.stat")).await.unwrap_or_default();
+    let (io_read, io_write) = read_v1_io(&controller_path("blkio").join("blkio.throttle.io_service_bytes")).await;
+
+    Ok(CgroupUsage {
+        cpu_usage: read_value(&controller_path("cpuacct").join("cpuacct.usage")).await?,
+        cpu_throttled_periods: cpu_stat.get("nr_throttled").cloned().unwrap_or(0),
+        cpu_throttled_time: cpu_stat.get("throttled_time").cloned().unwrap_or(0),
+
+        memory_usage: read_value(&controller_path("memory").join("memory.usage_in_bytes")).await.unwrap_or(0),
+        memory_limit: read_value(&controller_path("memory").join("memory.limit_in_bytes")).await.ok()
+            .filter(|v| *v < CGROUP_V1_UNLIMITED),
+
+        io_read,
+        io_write,
+
+        pids: read_value(&controller_path("pids").join("pids.current")).await.unwrap_or(0)
+    })
+}
+
+async fn read_value(path: &Path) -> Result<u64, MetricCollectionError> {
+    Ok(read_to_string(path).await?.trim().parse()?)
+}
+
+// "<key> <value>" per line, e.g. cpu.stat
+async fn read_flat_keyed(path: &Path) -> Result<HashMap<String, u64>, MetricCollectionError> {
+    Ok(read_to_string(path).await?.lines()
+        .filter_map(|line| {
+            let mut spl = line.split_whitespace();
+            Some((spl.next()?.to_string(), spl.next()?.parse().ok()?))
+        })
+        .collect())
+}
+
+// "<major>:<minor> rbytes=<n> wbytes=<n> rios=<n> ..." per device
+async fn read_v2_io(path: &Path) -> (u64, u64) {
+    let io_stat = read_to_string(path).await.unwrap_or_default();
+    let mut read = 0;
+    let mut write = 0;
+
+    for field in io_stat.split_whitespace() {
+        let mut spl = field.splitn(2, '=');
+        let key = spl.next().unwrap_or("");
+        let value: u64 = spl.next().and_then(|v| v.parse().ok()).unwrap_or(0);
+
+        match key {
+            "rbytes" => read += value,
+            "wbytes" => write += value,
+            _ => {}
+        }
+    }
+
+    (read, write)
+}
+
+// "<major>:<minor> <Read|Write|Sync|Async|Discard|Total> <n>" per device and operation
+async fn read_v1_io(path: &Path) -> (u64, u64) {
+    let io_stat = read_to_string(path).await.unwrap_or_default();
+    let mut read = 0;
+    let mut write = 0;
+
+    for line in io_stat.lines() {
+        let spl: Vec<&str> = line.split_whitespace().collect();
+        if spl.len() != 3 {
+            continue;
+        }
+
+        let value: u64 = spl[2].parse().unwrap_or(0);
+        match spl[1] {
+            "Read" => read += value,
+            "Write" => write += value,
+            _ => {}
+        }
+    }
+
+    (read, write)
+}
@@ -0,0 +1,197 @@
+use std::collections::HashMap;
+
+use async_std::fs::read_dir;
+use chrono::{Utc, DateTime, Duration};
+use futures::future::{join_all, try_join_all};
+use futures::StreamExt;
+use async_trait::async_trait;
+use serde::Serialize;
+
+use crate::database::Database;
+use crate::config::get_max_metrics_age;
+use crate::cgroup::stat::{CgroupUsage, get_cgroup_root, is_cgroup_v2, read_v2_usage};
+use crate::types::{Metric, MetricCollectionError, MetricSaveError, MetricCleanupError, MetricCollector, MetricEncodingError};
+
+#[derive(Debug, Clone)]
+pub struct InstantSystemdUnitMetric {
+    timestamp: DateTime<Utc>,
+    stat: Vec<InstantSystemdUnitMetricEntry>
+}
+
+#[derive(Debug, Clone)]
+pub struct InstantSystemdUnitMetricEntry {
+    unit: String,
+    usage: CgroupUsage
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SystemdUnitMetric {
+    timestamp: DateTime<Utc>,
+    stat: Vec<SystemdUnitMetricEntry>
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SystemdUnitMetricEntry {
+    unit: String,
+
+    cpu_usage: f64, // cores
+    memory_usage: u64,
+
+    io_read: f64,
+    io_write: f64,
+
+    tasks: u64
+}
+
+impl Metric for InstantSystemdUnitMetric {
+}
+
+pub struct SystemdUnitMetricCollector {
+    previous: Option<InstantSystemdUnitMetric>,
+    metric: Option<SystemdUnitMetric>
+}
+
+impl SystemdUnitMetricCollector {
+
+    pub fn new() -> Self {
+        SystemdUnitMetricCollector {
+            previous: None,
+            metric: None
+        }
+    }
+
+    async fn collect_metric(&self) -> Result<Box<InstantSystemdUnitMetric>, MetricCollectionError> {
+        let root = get_cgroup_root();
+        let system_slice = root.join("system.slice");
+
+        // per-unit accounting of cgroup v1 is spread across controllers and often disabled, so only v2 is supported
+        if !is_cgroup_v2(&root) || !system_slice.exists() {
+            return Err(MetricCollectionError::NotConfigured {
+                description: "systemd units are only monitored with cgroup v2".to_string()
+            });
+        }
+
+        let timestamp = Utc::now();
+
+        let mut entries = read_dir(&system_slice).await?;
+        let mut units: Vec<String> = Vec::new();
+
+        while let Some(entry) = entries.next().await {
+            let entry = match entry {
+                Ok(v) => v,
+                Err(_) => continue
+            };
+
+            if !entry.file_type().await.map(|v| v.is_dir()).unwrap_or(false) {
+                continue;
+            }
+
+            if let Some(name) = entry.file_name().to_str().filter(|v| v.ends_with(".service")) {
+                units.push(name.to_string());
+            }
+        }
+
+        let stat = join_all(units.into_iter().map(|unit| {
+            let path = system_slice.join(&unit);
+
+            async move {
+                read_v2_usage(&path).await.map(|usage| InstantSystemdUnitMetricEntry { unit, usage })
+            }
+        })).await.into_iter()
+            .filter_map(|v| v.ok()) // units may stop while we are reading them
+            .collect();
+
+        Ok(Box::new(InstantSystemdUnitMetric { timestamp, stat }))
+    }
+}
+
+#[async_trait]
+impl MetricCollector for SystemdUnitMetricCollector {
+
+    fn key(&self) -> String {
+        "systemd".to_string()
+    }
+
+    async fn collect(&mut self) -> Result<(), MetricCollectionError> {
+        let metric = self.collect_metric().await?;
+        if let Some(prev) = &self.previous {
+            self.metric = Some(systemd_metric_from_stats(prev, &metric));
+        }
+        self.previous = Some(*metric);
+
+        Ok(())
+    }
+
+    async fn save(&self, database: &Database, hostname: &str) -> Result<(), MetricSaveError> {
+        if let Some(metric) = &self.metric {
+            let timestamp = metric.timestamp.clone();
+
+            let futures = metric.clone().stat.into_iter()
+                .map(|entry| save_metric_entry(&database, hostname, &timestamp, entry));
+
+            try_join_all(futures).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn encode(&self) -> Result<String, MetricEncodingError> {
+        if let Some(metric) = &self.metric {
+            let v = serde_json::to_string(metric)?;
+            return Ok(v);
+        }
+
+        Err(MetricEncodingError::NoRecord)
+    }
+
+    async fn cleanup(&self, mut database: &Database) -> Result<(), MetricCleanupError> {
+        let min_timestamp = Utc::now() - get_max_metrics_age();
+
+        sqlx::query!("delete from metric_systemd_units where timestamp < $1 returning 1 as result", min_timestamp)
+            .fetch_one(&mut database).await?;
+
+        Ok(())
+    }
+}
+
+fn systemd_metric_from_stats(first: &InstantSystemdUnitMetric, second: &InstantSystemdUnitMetric) -> SystemdUnitMetric {
+    let time_diff = second.timestamp - first.timestamp;
+
+    let first_entries: HashMap<&String, &CgroupUsage> = first.stat.iter()
+        .map(|v| (&v.unit, &v.usage))
+        .collect();
+
+    let stat: Vec<SystemdUnitMetricEntry> = second.stat.iter()
+        .filter_map(|v| first_entries.get(&v.unit)
+            .map(|item| systemd_metric_entry_from_two_stats(time_diff, &v.unit, item, &v.usage))
+        )
+        .collect();
+
+    SystemdUnitMetric { stat, timestamp: second.timestamp }
+}
+
+fn systemd_metric_entry_from_two_stats(time_diff: Duration, unit: &str, first: &CgroupUsage, second: &CgroupUsage) -> SystemdUnitMetricEntry {
+    let diff = time_diff.num_milliseconds() as f64 / 1000.0; // seconds
+
+    SystemdUnitMetricEntry {
+        unit: unit.to_string(),
+
+        cpu_usage: second.cpu_usage.saturating_sub(first.cpu_usage) as f64 / 1_000_000_000.0 / diff,
+        memory_usage: second.memory_usage,
+
+        io_read: second.io_read.saturating_sub(first.io_read) as f64 / diff,
+        io_write: second.io_write.saturating_sub(first.io_write) as f64 / diff,
+
+        tasks: second.pids
+    }
+}
+
+async fn save_metric_entry(mut database: &Database, hostname: &str, timestamp: &DateTime<Utc>, entry: SystemdUnitMetricEntry) -> Result<(), MetricSaveError> {
+    sqlx::query!(
+        "insert into metric_systemd_units (hostname, timestamp, unit, cpu_usage, memory_usage, io_read, io_write, tasks) values ($1, $2, $3, $4, $5, $6, $7, $8) returning hostname",
+        hostname.to_string(), *timestamp, entry.unit, entry.cpu_usage, entry.memory_usage as i64,
+        entry.io_read, entry.io_write, entry.tasks as i32
+    ).fetch_one(&mut database).await?;
+
+    Ok(())
+}
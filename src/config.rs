@@ -2,6 +2,7 @@ use std::env;
 use chrono::Duration;
 
 const DEFAULT_REPORT_INTERVAL: u16 = 60; // every minute
+const DEFAULT_SLOW_REPORT_INTERVAL: i64 = 60 * 60; // every hour
 
 pub fn get_metric_report_interval() -> u16 {
     env::var("REPORT_INTERVAL").ok()
@@ -9,6 +10,14 @@ pub fn get_metric_report_interval() -> u16 {
         .unwrap_or(DEFAULT_REPORT_INTERVAL)
 }
 
+// for metrics which are expensive to collect and change slowly
+pub fn get_slow_metric_report_interval() -> Duration {
+    env::var("SLOW_REPORT_INTERVAL").ok()
+        .and_then(|v| v.parse::<i64>().ok())
+        .map(|v| Duration::seconds(v))
+        .unwrap_or(Duration::seconds(DEFAULT_SLOW_REPORT_INTERVAL))
+}
+
 pub fn get_max_metrics_age() -> Duration {
     env::var("MAX_METRICS_AGE").ok()
         .and_then(|v| v.parse::<i64>().ok())
@@ -0,0 +1,160 @@
+use std::path::Path;
+use std::collections::HashMap;
+
+use async_std::fs::read_to_string;
+use chrono::{Utc, DateTime};
+use async_trait::async_trait;
+use serde::Serialize;
+
+use crate::database::Database;
+use crate::config::get_max_metrics_age;
+use crate::types::{Metric, MetricCollectionError, MetricSaveError, MetricCleanupError, MetricCollector, MetricEncodingError};
+
+const CONNTRACK_COUNT_FILE: &str = "/proc/sys/net/netfilter/nf_conntrack_count";
+const CONNTRACK_MAX_FILE: &str = "/proc/sys/net/netfilter/nf_conntrack_max";
+const CONNTRACK_STAT_FILE: &str = "/proc/net/stat/nf_conntrack";
+
+#[derive(Debug, Clone)]
+pub struct InstantConntrackMetric {
+    timestamp: DateTime<Utc>,
+    count: u64,
+    max: u64,
+    drop: u64,
+    insert_failed: u64,
+    early_drop: u64
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ConntrackMetric {
+    timestamp: DateTime<Utc>,
+    count: u64,
+    max: u64,
+    usage: f64,
+    drop: f64,
+    insert_failed: f64,
+    early_drop: f64
+}
+
+impl Metric for InstantConntrackMetric {
+}
+
+pub struct ConntrackMetricCollector {
+    previous: Option<InstantConntrackMetric>,
+    metric: Option<ConntrackMetric>
+}
+
+impl ConntrackMetricCollector {
+
+    pub fn new() -> Self {
+        ConntrackMetricCollector {
+            previous: None,
+            metric: None
+        }
+    }
+
+    async fn collect_metric(&self) -> Result<Box<InstantConntrackMetric>, MetricCollectionError> {
+        if !Path::new(CONNTRACK_COUNT_FILE).exists() {
+            return Err(MetricCollectionError::NotConfigured {
+                description: "nf_conntrack module is not loaded".to_string()
+            });
+        }
+
+        let timestamp = Utc::now();
+
+        let count = read_to_string(CONNTRACK_COUNT_FILE).await?.trim().parse()?;
+        let max = read_to_string(CONNTRACK_MAX_FILE).await?.trim().parse()?;
+        let stat = parse_conntrack_stat(&read_to_string(CONNTRACK_STAT_FILE).await?)?;
+
+        Ok(Box::new(InstantConntrackMetric {
+            timestamp,
+            count,
+            max,
+            drop: *stat.get("drop").unwrap_or(&0),
+            insert_failed: *stat.get("insert_failed").unwrap_or(&0),
+            early_drop: *stat.get("early_drop").unwrap_or(&0)
+        }))
+    }
+}
+
+#[async_trait]
+impl MetricCollector for ConntrackMetricCollector {
+
+    fn key(&self) -> String {
+        "conntrack".to_string()
+    }
+
+    async fn collect(&mut self) -> Result<(), MetricCollectionError> {
+        let metric = self.collect_metric().await?;
+        if let Some(prev) = &self.previous {
+            self.metric = Some(conntrack_metric_from_stats(prev, &metric));
+        }
+        self.previous = Some(*metric);
+        Ok(())
+    }
+
+    async fn save(&self, mut database: &Database, hostname: &str) -> Result<(), MetricSaveError> {
+        if let Some(metric) = &self.metric {
+            sqlx::query!(
+                "insert into metric_conntrack (hostname, timestamp, count, max, usage, \"drop\", insert_failed, early_drop) values ($1, $2, $3, $4, $5, $6, $7, $8) returning hostname",
+                hostname.to_string(), metric.timestamp, metric.count as i64, metric.max as i64, metric.usage,
+                metric.drop, metric.insert_failed, metric.early_drop
+            ).fetch_one(&mut database).await?;
+        }
+        Ok(())
+    }
+
+    async fn encode(&self) -> Result<String, MetricEncodingError> {
+        if let Some(metric) = &self.metric {
+            let v = serde_json::to_string(metric)?;
+            return Ok(v);
+        }
+
+        Err(MetricEncodingError::NoRecord)
+    }
+
+    async fn cleanup(&self, mut database: &Database) -> Result<(), MetricCleanupError> {
+        let min_timestamp = Utc::now() - get_max_metrics_age();
+
+        sqlx::query!("delete from metric_conntrack where timestamp < $1 returning 1 as result", min_timestamp)
+            .fetch_one(&mut database).await?;
+
+        Ok(())
+    }
+}
+
+// /proc/net/stat/nf_conntrack has a header line with field names followed by one line of
+// hex counters per cpu. Counters are summed across cpus, except for "entries" which is global.
+fn parse_conntrack_stat(stat: &str) -> Result<HashMap<String, u64>, MetricCollectionError> {
+    let mut lines = stat.lines();
+    let fields: Vec<&str> = lines.next()?.split_whitespace().collect();
+
+    let mut result: HashMap<String, u64> = HashMap::new();
+
+    for line in lines {
+        for (field, value) in fields.iter().zip(line.split_whitespace()) {
+            let value = u64::from_str_radix(value, 16)?;
+            let entry = result.entry(field.to_string()).or_insert(0);
+            if *field == "entries" {
+                *entry = value;
+            } else {
+                *entry += value;
+            }
+        }
+    }
+
+    Ok(result)
+}
+
+fn conntrack_metric_from_stats(first: &InstantConntrackMetric, second: &InstantConntrackMetric) -> ConntrackMetric {
+    let diff = (second.timestamp - first.timestamp).num_milliseconds() as f64 / 1000.0; // seconds
+
+    ConntrackMetric {
+        timestamp: second.timestamp,
+        count: second.count,
+        max: second.max,
+        usage: if second.max > 0 { second.count as f64 / second.max as f64 } else { 0.0 },
+        drop: second.drop.saturating_sub(first.drop) as f64 / diff,
+        insert_failed: second.insert_failed.saturating_sub(first.insert_failed) as f64 / diff,
+        early_drop: second.early_drop.saturating_sub(first.early_drop) as f64 / diff
+    }
+}
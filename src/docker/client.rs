@@ -1,14 +1,28 @@
 use hyper::{Method, Request, Body, Client};
-use hyperlocal::{Uri, UnixClientExt};
-use futures::TryStreamExt;
+use hyper::body::Bytes;
+use hyperlocal::{Uri, UnixClientExt, UnixConnector};
+use futures::StreamExt;
 use custom_error::custom_error;
 use serde::Deserialize;
 use serde::de::DeserializeOwned;
-use std::path::Path;
+use std::env;
+use std::fs::read;
+use std::path::{Path, PathBuf};
 use std::collections::HashMap;
+use std::time::Duration;
+
+use async_std::future::timeout;
+use log::warn;
+
+const DEFAULT_DOCKER_HOST: &str = "unix:///var/run/docker.sock";
+const DEFAULT_DOCKER_REQUEST_TIMEOUT: u64 = 10; // seconds
+const DOCKER_DISK_USAGE_TIMEOUT: u64 = 5 * 60; // seconds, docker walks all layers and volumes to calculate it
 
 custom_error! {pub DockerClientError
-    RequestToDockerFailed = "request to docker failed"
+    RequestToDockerFailed = "request to docker failed",
+    RequestTimedOut = "request to docker timed out",
+    ErrorResponse{status: u16, message: String} = "docker responded with status {status}: {message}",
+    InvalidConfiguration{description: String} = "invalid docker client configuration: {description}"
 }
 
 impl From<hyper::error::Error> for DockerClientError {
@@ -23,6 +37,12 @@ impl From<http::Error> for DockerClientError {
     }
 }
 
+impl From<reqwest::Error> for DockerClientError {
+    fn from(_err: reqwest::Error) -> Self {
+        DockerClientError::RequestToDockerFailed
+    }
+}
+
 impl From<serde_json::error::Error> for DockerClientError {
     fn from(_err: serde_json::error::Error) -> Self {
         DockerClientError::RequestToDockerFailed
@@ -30,73 +50,464 @@ impl From<serde_json::error::Error> for DockerClientError {
 }
 
 #[derive(Deserialize, Debug)]
+struct ErrorMessage {
+    message: String
+}
+
+#[derive(Deserialize, Debug, Clone)]
 pub struct Container {
     #[serde(rename = "Id")]
     pub id: String,
+    #[serde(rename = "Names", default)]
+    pub names: Vec<String>,
     #[serde(rename = "State")]
     pub state: String,
 }
 
 #[derive(Deserialize, Debug)]
+pub struct ContainerDetails {
+    #[serde(rename = "Id")]
+    pub id: String,
+    #[serde(rename = "Name")]
+    pub name: String,
+    #[serde(rename = "Created")]
+    pub created: String,
+    #[serde(rename = "RestartCount")]
+    pub restart_count: u32,
+    #[serde(rename = "State")]
+    pub state: ContainerState,
+    #[serde(rename = "Config")]
+    pub config: ContainerConfig,
+    #[serde(rename = "NetworkSettings")]
+    pub network_settings: Option<NetworkSettings>
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ContainerState {
+    #[serde(rename = "Status")]
+    pub status: String,
+    #[serde(rename = "ExitCode")]
+    pub exit_code: i32,
+    #[serde(rename = "StartedAt")]
+    pub started_at: String,
+    #[serde(rename = "Pid", default)]
+    pub pid: u32, // 0 when not running
+    #[serde(rename = "Health")]
+    pub health: Option<ContainerHealth>
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ContainerHealth {
+    #[serde(rename = "Status")]
+    pub status: String
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ContainerConfig {
+    #[serde(rename = "Image")]
+    pub image: String,
+    #[serde(rename = "Labels")]
+    pub labels: Option<HashMap<String, String>>
+}
+
+#[derive(Deserialize, Debug)]
+pub struct NetworkSettings {
+    #[serde(rename = "Networks")]
+    pub networks: Option<HashMap<String, EndpointSettings>> // by network name
+}
+
+#[derive(Deserialize, Debug)]
+pub struct EndpointSettings {
+    #[serde(rename = "MacAddress", default)]
+    pub mac_address: String
+}
+
+#[derive(Deserialize, Debug, Clone)]
 pub struct ContainerStats {
     pub name: String,
     pub cpu_stats: CPUStats,
     pub memory_stats: MemoryStats,
+    pub blkio_stats: BlkioStats,
+    #[serde(default)]
+    pub pids_stats: PidsStats,
+    #[serde(default)]
     pub networks: HashMap<String, NetworkStat>
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Clone)]
 pub struct CPUStats {
     pub cpu_usage: CPUUsage,
-    pub system_cpu_usage: u128
+    pub system_cpu_usage: u128,
+    pub online_cpus: Option<u32>,
+    #[serde(default)]
+    pub throttling_data: ThrottlingData
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Clone)]
 pub struct CPUUsage {
-    pub total_usage: u128
+    pub total_usage: u128,
+    pub percpu_usage: Option<Vec<u64>>
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct ThrottlingData {
+    pub periods: u64,
+    pub throttled_periods: u64,
+    pub throttled_time: u64
+}
+
+#[derive(Deserialize, Debug, Clone)]
 pub struct MemoryStats {
     pub usage: u64,
+    pub limit: u64,
     pub stats: MemoryUsageStats
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Clone)]
 pub struct MemoryUsageStats {
-    pub cache: u64
+    pub total_inactive_file: Option<u64>, // cgroup v1
+    pub inactive_file: Option<u64> // cgroup v2
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Clone)]
+pub struct BlkioStats {
+    pub io_service_bytes_recursive: Option<Vec<BlkioStatEntry>>,
+    pub io_serviced_recursive: Option<Vec<BlkioStatEntry>>
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct BlkioStatEntry {
+    pub op: String,
+    pub value: u64
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct PidsStats {
+    #[serde(default)]
+    pub current: u64
+}
+
+#[derive(Deserialize, Debug, Clone)]
 pub struct NetworkStat {
     pub rx_bytes: u64,
-    pub tx_bytes: u64
+    #[serde(default)]
+    pub rx_packets: u64,
+    #[serde(default)]
+    pub rx_errors: u64,
+    #[serde(default)]
+    pub rx_dropped: u64,
+    pub tx_bytes: u64,
+    #[serde(default)]
+    pub tx_packets: u64,
+    #[serde(default)]
+    pub tx_errors: u64,
+    #[serde(default)]
+    pub tx_dropped: u64
+}
+
+#[derive(Deserialize, Debug)]
+pub struct DiskUsage {
+    #[serde(rename = "LayersSize", default)]
+    pub layers_size: i64,
+    #[serde(rename = "Images")]
+    pub images: Option<Vec<ImageDiskUsage>>,
+    #[serde(rename = "Containers")]
+    pub containers: Option<Vec<ContainerDiskUsage>>,
+    #[serde(rename = "Volumes")]
+    pub volumes: Option<Vec<VolumeDiskUsage>>,
+    #[serde(rename = "BuildCache")]
+    pub build_cache: Option<Vec<BuildCacheDiskUsage>>
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ImageDiskUsage {
+    #[serde(rename = "Size")]
+    pub size: i64,
+    #[serde(rename = "SharedSize")]
+    pub shared_size: i64,
+    #[serde(rename = "Containers")]
+    pub containers: i64
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ContainerDiskUsage {
+    #[serde(rename = "SizeRw", default)]
+    pub size_rw: i64
+}
+
+#[derive(Deserialize, Debug)]
+pub struct VolumeDiskUsage {
+    #[serde(rename = "Name")]
+    pub name: String,
+    #[serde(rename = "UsageData")]
+    pub usage_data: Option<VolumeUsageData>
+}
+
+#[derive(Deserialize, Debug)]
+pub struct VolumeUsageData {
+    #[serde(rename = "Size")]
+    pub size: i64, // -1 if not available
+    #[serde(rename = "RefCount")]
+    pub ref_count: i64
+}
+
+#[derive(Deserialize, Debug)]
+pub struct BuildCacheDiskUsage {
+    #[serde(rename = "Size")]
+    pub size: i64,
+    #[serde(rename = "InUse")]
+    pub in_use: bool,
+    #[serde(rename = "Shared")]
+    pub shared: bool
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Event {
+    #[serde(rename = "Type")]
+    pub event_type: String,
+    #[serde(rename = "Action")]
+    pub action: String,
+    #[serde(rename = "Actor")]
+    pub actor: EventActor,
+    #[serde(rename = "timeNano")]
+    pub time_nano: i64
 }
 
-pub async fn containers() -> Result<Vec<Container>, DockerClientError> {
-    request(Method::GET, "/containers/json", "".to_string()).await
+#[derive(Deserialize, Debug, Clone)]
+pub struct EventActor {
+    #[serde(rename = "ID")]
+    pub id: String,
+    #[serde(rename = "Attributes", default)]
+    pub attributes: HashMap<String, String>
+}
+
+// Connection to docker daemon, configured with the same environment variables as docker cli:
+// DOCKER_HOST (unix:// or tcp://), DOCKER_TLS_VERIFY, DOCKER_CERT_PATH and DOCKER_API_VERSION.
+// Underlying http clients keep a connection pool, so this is meant to be created once and cloned.
+#[derive(Clone)]
+pub struct DockerClient {
+    transport: Transport,
+    api_version: Option<String>,
+    request_timeout: Duration
+}
+
+#[derive(Clone)]
+enum Transport {
+    Unix { client: Client<UnixConnector>, socket: PathBuf },
+    Tcp { client: reqwest::Client, base_url: String }
 }
 
-pub async fn stats(container_id: String) -> Result<ContainerStats, DockerClientError> {
-    request(Method::GET, &format!("/containers/{}/stats?stream=false", container_id), "".to_string()).await
+enum ResponseBody {
+    Unix(Body),
+    Tcp(reqwest::Response)
 }
 
-async fn request<T: DeserializeOwned>(method: Method, url: &str, body: String) -> Result<T, DockerClientError> {
-    let client = Client::unix();
+impl DockerClient {
+
+    pub fn from_env() -> Result<Self, DockerClientError> {
+        let host = env::var("DOCKER_HOST").unwrap_or(DEFAULT_DOCKER_HOST.to_string());
+
+        let transport = if host.starts_with("unix://") {
+            Transport::Unix {
+                client: Client::unix(),
+                socket: PathBuf::from(&host["unix://".len()..])
+            }
+        } else if host.starts_with("tcp://") {
+            let tls = env::var("DOCKER_TLS_VERIFY").map(|v| !v.is_empty() && v != "0").unwrap_or(false);
+            let scheme = if tls { "https" } else { "http" };
+
+            Transport::Tcp {
+                client: tcp_client(tls)?,
+                base_url: format!("{}://{}", scheme, &host["tcp://".len()..])
+            }
+        } else {
+            return Err(DockerClientError::InvalidConfiguration {
+                description: format!("unsupported DOCKER_HOST: {}", host)
+            });
+        };
+
+        Ok(DockerClient {
+            transport,
+            api_version: env::var("DOCKER_API_VERSION").ok(),
+            request_timeout: Duration::from_secs(get_docker_request_timeout())
+        })
+    }
+
+    pub async fn containers(&self) -> Result<Vec<Container>, DockerClientError> {
+        self.request(Method::GET, "/containers/json?all=true", "".to_string()).await
+    }
+
+    pub async fn inspect(&self, container_id: String) -> Result<ContainerDetails, DockerClientError> {
+        self.request(Method::GET, &format!("/containers/{}/json", container_id), "".to_string()).await
+    }
+
+    pub async fn stats(&self, container_id: String) -> Result<ContainerStats, DockerClientError> {
+        self.request(Method::GET, &format!("/containers/{}/stats?stream=false", container_id), "".to_string()).await
+    }
+
+    pub async fn disk_usage(&self) -> Result<DiskUsage, DockerClientError> {
+        let timeout = Duration::from_secs(DOCKER_DISK_USAGE_TIMEOUT);
+        self.request_with_timeout(timeout, Method::GET, "/system/df", "".to_string()).await
+    }
+
+    // Keeps receiving stats of a running container, calling on_stats for each sample (about once a second).
+    // Returns once the container stops.
+    pub async fn stats_stream<F: FnMut(ContainerStats)>(&self, container_id: String, on_stats: F) -> Result<(), DockerClientError> {
+        self.stream(&format!("/containers/{}/stats?stream=true", container_id), on_stats).await
+    }
+
+    // Subscribes to container events, starting from the given time (unix nanoseconds) if any, calling on_event for
+    // each of them. Returns once docker closes the stream.
+    pub async fn events<F: FnMut(Event)>(&self, since: Option<i64>, on_event: F) -> Result<(), DockerClientError> {
+        // filters={"type":["container"]}
+        let mut url = "/events?filters=%7B%22type%22%3A%5B%22container%22%5D%7D".to_string();
+        if let Some(since) = since {
+            url.push_str(&format!("&since={}.{:09}", since / 1_000_000_000, since % 1_000_000_000));
+        }
+
+        self.stream(&url, on_event).await
+    }
+
+    async fn request<T: DeserializeOwned>(&self, method: Method, url: &str, body: String) -> Result<T, DockerClientError> {
+        self.request_with_timeout(self.request_timeout, method, url, body).await
+    }
+
+    async fn request_with_timeout<T: DeserializeOwned>(&self, request_timeout: Duration, method: Method, url: &str, body: String) -> Result<T, DockerClientError> {
+        timeout(request_timeout, self.request_without_timeout(method, url, body)).await
+            .map_err(|_| DockerClientError::RequestTimedOut)?
+    }
+
+    async fn request_without_timeout<T: DeserializeOwned>(&self, method: Method, url: &str, body: String) -> Result<T, DockerClientError> {
+        let bytes = self.send(method, url, body).await?.bytes().await?;
+        Ok(serde_json::from_slice(&bytes)?)
+    }
+
+    async fn stream<T: DeserializeOwned, F: FnMut(T)>(&self, url: &str, mut on_item: F) -> Result<(), DockerClientError> {
+        let mut body = self.send(Method::GET, url, "".to_string()).await?;
+        let mut buf: Vec<u8> = Vec::new();
 
-    let req = Request::builder()
-        .uri(Uri::new(Path::new("/var/run/docker.sock"), url))
-        .header("Content-Type", "application/json")
-        .header("Accept", "application/json")
-        .method(method)
-        .body(Body::from(body))?;
+        // items are newline-delimited json objects, which are not aligned with chunk boundaries
+        while let Some(chunk) = body.chunk().await? {
+            buf.extend(chunk);
 
-    let res = client.request(req).await?.into_body();
+            while let Some(pos) = buf.iter().position(|b| *b == b'\n') {
+                let line: Vec<u8> = buf.drain(..=pos).collect();
+                if line.iter().all(|b| b.is_ascii_whitespace()) {
+                    continue;
+                }
 
-    let bytes = res.try_fold(Vec::default(), |mut buf, bytes| async {
-        buf.extend(bytes);
-        Ok(buf)
-    }).await?;
+                // one item which can not be parsed should not end the whole stream
+                match serde_json::from_slice(&line) {
+                    Ok(item) => on_item(item),
+                    Err(err) => warn!("failed to parse docker stream item: {}", err)
+                }
+            }
+        }
+
+        Ok(())
+    }
 
-    Ok(serde_json::from_slice(&bytes)?)
-}
\ No newline at end of file
+    async fn send(&self, method: Method, url: &str, body: String) -> Result<ResponseBody, DockerClientError> {
+        let url = match &self.api_version {
+            Some(version) => format!("/v{}{}", version, url),
+            None => url.to_string()
+        };
+
+        let (status, mut body) = match &self.transport {
+            Transport::Unix { client, socket } => {
+                let req = Request::builder()
+                    .uri(Uri::new(socket, &url))
+                    .header("Content-Type", "application/json")
+                    .header("Accept", "application/json")
+                    .method(method)
+                    .body(Body::from(body))?;
+
+                let res = client.request(req).await?;
+                (res.status(), ResponseBody::Unix(res.into_body()))
+            },
+            Transport::Tcp { client, base_url } => {
+                let res = client.request(method, &format!("{}{}", base_url, url))
+                    .header("Content-Type", "application/json")
+                    .header("Accept", "application/json")
+                    .body(body)
+                    .send().await?;
+
+                (res.status(), ResponseBody::Tcp(res))
+            }
+        };
+
+        // e.g. 404 when a container was removed after it was listed, docker explains the error in the body
+        if !status.is_success() {
+            let bytes = body.bytes().await?;
+            let message = serde_json::from_slice::<ErrorMessage>(&bytes)
+                .map(|v| v.message)
+                .unwrap_or_else(|_| String::from_utf8_lossy(&bytes).trim().to_string());
+
+            return Err(DockerClientError::ErrorResponse { status: status.as_u16(), message });
+        }
+
+        Ok(body)
+    }
+}
+
+impl ResponseBody {
+
+    async fn chunk(&mut self) -> Result<Option<Bytes>, DockerClientError> {
+        match self {
+            ResponseBody::Unix(body) => Ok(body.next().await.transpose()?),
+            ResponseBody::Tcp(res) => Ok(res.chunk().await?)
+        }
+    }
+
+    async fn bytes(&mut self) -> Result<Vec<u8>, DockerClientError> {
+        let mut bytes = Vec::default();
+
+        while let Some(chunk) = self.chunk().await? {
+            bytes.extend(chunk);
+        }
+
+        Ok(bytes)
+    }
+}
+
+fn get_docker_request_timeout() -> u64 {
+    env::var("DOCKER_REQUEST_TIMEOUT").ok()
+        .and_then(|v| v.parse::<u64>().ok())
+        .unwrap_or(DEFAULT_DOCKER_REQUEST_TIMEOUT)
+}
+
+fn tcp_client(tls: bool) -> Result<reqwest::Client, DockerClientError> {
+    let mut builder = reqwest::Client::builder();
+
+    if tls {
+        let cert_path = env::var("DOCKER_CERT_PATH").map_err(|_| DockerClientError::InvalidConfiguration {
+            description: "DOCKER_CERT_PATH is required when DOCKER_TLS_VERIFY is set".to_string()
+        })?;
+        let cert_path = Path::new(&cert_path);
+
+        let ca = read_cert_file(&cert_path.join("ca.pem"))?;
+
+        // reqwest expects client certificate and private key in a single pem
+        let mut identity = read_cert_file(&cert_path.join("cert.pem"))?;
+        identity.extend(read_cert_file(&cert_path.join("key.pem"))?);
+
+        builder = builder
+            .use_rustls_tls()
+            .add_root_certificate(reqwest::Certificate::from_pem(&ca).map_err(invalid_certificate)?)
+            .identity(reqwest::Identity::from_pem(&identity).map_err(invalid_certificate)?);
+    }
+
+    Ok(builder.build()?)
+}
+
+fn read_cert_file(path: &Path) -> Result<Vec<u8>, DockerClientError> {
+    read(path).map_err(|err| DockerClientError::InvalidConfiguration {
+        description: format!("failed to read {}: {}", path.display(), err)
+    })
+}
+
+fn invalid_certificate(err: reqwest::Error) -> DockerClientError {
+    DockerClientError::InvalidConfiguration {
+        description: format!("invalid certificate: {}", err)
+    }
+}
@@ -0,0 +1,201 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use async_std::task;
+use chrono::{Utc, DateTime};
+use futures::future::{try_join_all, try_join};
+use log::warn;
+use async_trait::async_trait;
+use serde::Serialize;
+
+use crate::database::Database;
+use crate::docker::client::{DockerClient, DiskUsage};
+use crate::config::{get_max_metrics_age, get_slow_metric_report_interval};
+use crate::types::{Metric, MetricCollectionError, MetricSaveError, MetricCleanupError, MetricCollector, MetricEncodingError};
+
+const RETRY_INTERVAL: u64 = 5 * 60; // seconds
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DockerDiskUsageMetric {
+    timestamp: DateTime<Utc>,
+
+    images: i64,
+    images_size: i64,
+    images_reclaimable: i64,
+
+    containers_size: i64,
+
+    volumes: i64,
+    volumes_size: i64,
+    volumes_reclaimable: i64,
+
+    build_cache_size: i64,
+    build_cache_reclaimable: i64,
+
+    volume_stat: Vec<DockerVolumeMetricEntry>
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DockerVolumeMetricEntry {
+    name: String,
+    size: Option<i64>, // none when docker did not calculate it
+    ref_count: Option<i64>
+}
+
+impl Metric for DockerDiskUsageMetric {
+}
+
+pub struct DockerDiskUsageCollector {
+    latest: Arc<Mutex<Option<DockerDiskUsageMetric>>>, // not reported yet
+    metric: Option<DockerDiskUsageMetric>
+}
+
+impl DockerDiskUsageCollector {
+
+    pub fn new(client: DockerClient) -> Self {
+        let latest = Arc::new(Mutex::new(None));
+        tokio::spawn(request_disk_usage(client, latest.clone()));
+
+        DockerDiskUsageCollector {
+            latest,
+            metric: None
+        }
+    }
+}
+
+#[async_trait]
+impl MetricCollector for DockerDiskUsageCollector {
+
+    fn key(&self) -> String {
+        "docker_disk_usage".to_string()
+    }
+
+    async fn collect(&mut self) -> Result<(), MetricCollectionError> {
+        // each result is reported once, nothing is reported until the next one is ready
+        self.metric = self.latest.lock()
+            .map(|mut latest| latest.take())
+            .unwrap_or_default();
+
+        Ok(())
+    }
+
+    async fn save(&self, mut database: &Database, hostname: &str) -> Result<(), MetricSaveError> {
+        if let Some(metric) = &self.metric {
+            let timestamp = metric.timestamp.clone();
+
+            sqlx::query!(
+                "insert into metric_docker_disk_usage (hostname, timestamp, images, images_size, images_reclaimable, containers_size, volumes, volumes_size, volumes_reclaimable, build_cache_size, build_cache_reclaimable) values ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11) returning hostname",
+                hostname.to_string(), timestamp, metric.images, metric.images_size, metric.images_reclaimable,
+                metric.containers_size, metric.volumes, metric.volumes_size, metric.volumes_reclaimable,
+                metric.build_cache_size, metric.build_cache_reclaimable
+            ).fetch_one(&mut database).await?;
+
+            let futures = metric.clone().volume_stat.into_iter()
+                .map(|entry| save_volume_entry(&database, hostname, &timestamp, entry));
+
+            try_join_all(futures).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn encode(&self) -> Result<String, MetricEncodingError> {
+        if let Some(metric) = &self.metric {
+            let v = serde_json::to_string(metric)?;
+            return Ok(v);
+        }
+
+        Err(MetricEncodingError::NoRecord)
+    }
+
+    async fn cleanup(&self, mut database: &Database) -> Result<(), MetricCleanupError> {
+        let min_timestamp = Utc::now() - get_max_metrics_age();
+
+        try_join(
+            sqlx::query!("delete from metric_docker_disk_usage where timestamp < $1 returning 1 as result", min_timestamp)
+                .fetch_one(&mut database.clone()),
+            sqlx::query!("delete from metric_docker_volumes where timestamp < $1 returning 1 as result", min_timestamp)
+                .fetch_one(&mut database)
+        ).await?;
+
+        Ok(())
+    }
+}
+
+// Calculating disk usage is expensive for docker and may take minutes, so it is requested in the background on a slower
+// interval and does not hold up other collectors.
+async fn request_disk_usage(client: DockerClient, latest: Arc<Mutex<Option<DockerDiskUsageMetric>>>) {
+    let mut is_failing = false;
+
+    loop {
+        let interval = match client.disk_usage().await {
+            Ok(usage) => {
+                if let Ok(mut latest) = latest.lock() {
+                    *latest = Some(docker_disk_usage_metric(Utc::now(), usage));
+                }
+
+                is_failing = false;
+                get_slow_metric_report_interval().num_seconds().max(0) as u64
+            },
+            Err(err) => {
+                // only the first failure is logged, docker may be not running at all
+                if !is_failing {
+                    warn!("failed to get docker disk usage: {}", err);
+                }
+
+                is_failing = true;
+                RETRY_INTERVAL
+            }
+        };
+
+        task::sleep(Duration::from_secs(interval)).await;
+    }
+}
+
+// Follows the way `docker system df` calculates totals and reclaimable space.
+fn docker_disk_usage_metric(timestamp: DateTime<Utc>, usage: DiskUsage) -> DockerDiskUsageMetric {
+    let images = usage.images.unwrap_or_default();
+    let containers = usage.containers.unwrap_or_default();
+    let volumes = usage.volumes.unwrap_or_default();
+    let build_cache = usage.build_cache.unwrap_or_default();
+
+    // docker reports -1 for sizes and reference counts which were not calculated
+    let volume_stat: Vec<DockerVolumeMetricEntry> = volumes.into_iter()
+        .map(|v| DockerVolumeMetricEntry {
+            name: v.name,
+            size: v.usage_data.as_ref().map(|usage| usage.size).filter(|v| *v >= 0),
+            ref_count: v.usage_data.as_ref().map(|usage| usage.ref_count).filter(|v| *v >= 0)
+        })
+        .collect();
+
+    DockerDiskUsageMetric {
+        timestamp,
+
+        images: images.len() as i64,
+        images_size: usage.layers_size,
+        images_reclaimable: images.iter()
+            .filter(|v| v.containers == 0)
+            .map(|v| v.size - v.shared_size.max(0))
+            .sum(),
+
+        containers_size: containers.iter().map(|v| v.size_rw).sum(),
+
+        volumes: volume_stat.len() as i64,
+        volumes_size: volume_stat.iter().filter_map(|v| v.size).sum(),
+        volumes_reclaimable: volume_stat.iter().filter(|v| v.ref_count == Some(0)).filter_map(|v| v.size).sum(),
+
+        build_cache_size: build_cache.iter().map(|v| v.size).sum(),
+        build_cache_reclaimable: build_cache.iter().filter(|v| !v.in_use && !v.shared).map(|v| v.size).sum(),
+
+        volume_stat
+    }
+}
+
+async fn save_volume_entry(mut database: &Database, hostname: &str, timestamp: &DateTime<Utc>, entry: DockerVolumeMetricEntry) -> Result<(), MetricSaveError> {
+    sqlx::query!(
+        "insert into metric_docker_volumes (hostname, timestamp, name, size, ref_count) values ($1, $2, $3, $4, $5) returning hostname",
+        hostname.to_string(), *timestamp, entry.name, entry.size, entry.ref_count.map(|v| v as i32)
+    ).fetch_one(&mut database).await?;
+
+    Ok(())
+}
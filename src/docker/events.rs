@@ -0,0 +1,229 @@
+use std::sync::{Arc, Mutex};
+use std::collections::HashSet;
+use std::time::Duration;
+
+use async_std::task;
+use chrono::{Utc, DateTime, TimeZone};
+use futures::future::join_all;
+use log::warn;
+use async_trait::async_trait;
+use serde::Serialize;
+
+use crate::database::Database;
+use crate::docker::client::{DockerClient, Event};
+use crate::config::get_max_metrics_age;
+use crate::types::{Metric, MetricCollectionError, MetricSaveError, MetricCleanupError, MetricCollector, MetricEncodingError};
+
+const RECONNECT_INTERVAL: u64 = 10; // seconds
+const MAX_RECONNECT_INTERVAL: u64 = 10 * 60; // seconds, e.g. when docker is not installed
+const MAX_BUFFERED_EVENTS: usize = 10_000; // in case events are not drained, e.g. database is down
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DockerEventsMetric {
+    stat: Vec<DockerEventEntry>
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DockerEventEntry {
+    timestamp: DateTime<Utc>,
+    container_id: String,
+    name: Option<String>,
+    action: String,
+    exit_code: Option<i32>,
+    attributes: String
+}
+
+impl Metric for DockerEventsMetric {
+}
+
+// Ids of containers which had a tracked event (start, die, health status change, ...) since they were last taken,
+// so that other collectors know when their cached container details are outdated.
+#[derive(Clone, Default)]
+pub struct ContainerChanges {
+    ids: Arc<Mutex<HashSet<String>>>
+}
+
+pub struct DockerEventsCollector {
+    buffer: Arc<Mutex<Vec<DockerEventEntry>>>,
+    changes: ContainerChanges,
+    metric: Option<DockerEventsMetric>
+}
+
+impl DockerEventsCollector {
+
+    pub fn new(client: DockerClient) -> Self {
+        let buffer = Arc::new(Mutex::new(Vec::new()));
+        let changes = ContainerChanges::default();
+        tokio::spawn(subscribe_to_events(client, buffer.clone(), changes.clone()));
+
+        DockerEventsCollector {
+            buffer,
+            changes,
+            metric: None
+        }
+    }
+
+    pub fn changes(&self) -> ContainerChanges {
+        self.changes.clone()
+    }
+}
+
+impl ContainerChanges {
+
+    pub fn take(&self) -> HashSet<String> {
+        self.ids.lock()
+            .map(|mut ids| ids.drain().collect())
+            .unwrap_or_default()
+    }
+
+    fn add(&self, container_id: &str) {
+        if let Ok(mut ids) = self.ids.lock() {
+            ids.insert(container_id.to_string());
+        }
+    }
+}
+
+#[async_trait]
+impl MetricCollector for DockerEventsCollector {
+
+    fn key(&self) -> String {
+        "docker_events".to_string()
+    }
+
+    async fn collect(&mut self) -> Result<(), MetricCollectionError> {
+        // events stay buffered until they are saved, this only reports what is pending
+        let stat = self.buffer.lock()
+            .map(|buffer| buffer.clone())
+            .unwrap_or_default();
+
+        self.metric = Some(DockerEventsMetric { stat });
+
+        Ok(())
+    }
+
+    async fn save(&self, database: &Database, hostname: &str) -> Result<(), MetricSaveError> {
+        let pending: Vec<DockerEventEntry> = self.buffer.lock()
+            .map(|mut buffer| buffer.drain(..).collect())
+            .unwrap_or_default();
+
+        let results = join_all(pending.into_iter().map(|entry| async move {
+            save_event_entry(&database, hostname, entry.clone()).await.map_err(|err| (entry, err))
+        })).await;
+
+        let mut failed = Vec::new();
+        let mut error = None;
+        for result in results {
+            if let Err((entry, err)) = result {
+                failed.push(entry);
+                error = Some(err);
+            }
+        }
+
+        // events which were not saved (e.g. database is down) are retried on the next save, before newer ones
+        if let Some(err) = error {
+            if let Ok(mut buffer) = self.buffer.lock() {
+                failed.extend(buffer.drain(..));
+                *buffer = failed;
+            }
+
+            return Err(err);
+        }
+
+        Ok(())
+    }
+
+    async fn encode(&self) -> Result<String, MetricEncodingError> {
+        if let Some(metric) = &self.metric {
+            let v = serde_json::to_string(metric)?;
+            return Ok(v);
+        }
+
+        Err(MetricEncodingError::NoRecord)
+    }
+
+    async fn cleanup(&self, mut database: &Database) -> Result<(), MetricCleanupError> {
+        let min_timestamp = Utc::now() - get_max_metrics_age();
+
+        sqlx::query!("delete from metric_docker_events where timestamp < $1 returning 1 as result", min_timestamp)
+            .fetch_one(&mut database).await?;
+
+        Ok(())
+    }
+}
+
+async fn subscribe_to_events(client: DockerClient, buffer: Arc<Mutex<Vec<DockerEventEntry>>>, changes: ContainerChanges) {
+    let mut last_event_time: Option<i64> = None; // nanoseconds
+    let mut is_buffer_full = false;
+    let mut failures: u32 = 0;
+
+    loop {
+        // events which happened while reconnecting are replayed by docker, the last one received is replayed too
+        let since = last_event_time;
+        let result = client.events(since, |event| {
+            if since.map(|v| event.time_nano <= v).unwrap_or(false) {
+                return;
+            }
+            last_event_time = Some(event.time_nano);
+
+            if !is_tracked_event(&event) {
+                return;
+            }
+
+            changes.add(&event.actor.id);
+
+            if let Ok(mut buffer) = buffer.lock() {
+                if buffer.len() < MAX_BUFFERED_EVENTS {
+                    buffer.push(docker_event_entry(event));
+                    is_buffer_full = false;
+                } else if !is_buffer_full {
+                    warn!("too many docker events buffered, dropping new ones until they are saved");
+                    is_buffer_full = true;
+                }
+            }
+        }).await;
+
+        match result {
+            Ok(()) => {
+                warn!("docker events stream closed, reconnecting...");
+                failures = 0;
+            },
+            Err(err) => {
+                // only the first failure is logged, docker may be not installed at all
+                if failures == 0 {
+                    warn!("failed to subscribe to docker events, retrying with backoff: {}", err);
+                }
+                failures += 1;
+            }
+        }
+
+        let interval = (RECONNECT_INTERVAL << failures.min(6)).min(MAX_RECONNECT_INTERVAL);
+        task::sleep(Duration::from_secs(interval)).await;
+    }
+}
+
+fn is_tracked_event(event: &Event) -> bool {
+    event.event_type == "container" && (
+        event.action == "start" || event.action == "die" || event.action == "oom" || event.action == "kill"
+            || event.action.starts_with("health_status")
+    )
+}
+
+fn docker_event_entry(event: Event) -> DockerEventEntry {
+    DockerEventEntry {
+        timestamp: Utc.timestamp_nanos(event.time_nano),
+        container_id: event.actor.id,
+        name: event.actor.attributes.get("name").cloned(),
+        action: event.action,
+        exit_code: event.actor.attributes.get("exitCode").and_then(|v| v.parse().ok()),
+        attributes: serde_json::to_string(&event.actor.attributes).unwrap_or_default()
+    }
+}
+
+async fn save_event_entry(mut database: &Database, hostname: &str, entry: DockerEventEntry) -> Result<(), MetricSaveError> {
+    sqlx::query!(
+        "insert into metric_docker_events (hostname, timestamp, container_id, name, action, exit_code, attributes) values ($1, $2, $3, $4, $5, $6, $7) returning hostname",
+        hostname.to_string(), entry.timestamp, entry.container_id, entry.name, entry.action, entry.exit_code, entry.attributes
+    ).fetch_one(&mut database).await?;
+
+    Ok(())
+}
@@ -1,17 +1,26 @@
-use chrono::{Utc, DateTime, Duration};
+use std::env;
+use std::sync::{Arc, Mutex};
+use std::collections::{HashMap, HashSet};
+
+use async_std::fs::read_to_string;
+use chrono::{Utc, DateTime, Duration, Datelike};
 use custom_error::custom_error;
-use futures::future::{join_all, try_join_all};
+use futures::future::{try_join_all, try_join};
+use futures::stream::{self, StreamExt};
 use log::warn;
 
 use async_trait::async_trait;
 use serde::Serialize;
 
 use crate::database::Database;
-use crate::docker::client::{containers, DockerClientError, stats, Container, ContainerStats};
+use crate::docker::client::{DockerClient, DockerClientError, Container, ContainerStats, ContainerDetails, BlkioStatEntry, NetworkStat};
+use crate::docker::events::ContainerChanges;
 use futures::FutureExt;
 use crate::config::get_max_metrics_age;
 use crate::types::{Metric, MetricCollectionError, MetricSaveError, MetricCleanupError, MetricCollector, MetricEncodingError};
 
+const DEFAULT_DOCKER_STATS_CONCURRENCY: usize = 8;
+
 #[derive(Debug, Clone)]
 pub struct InstantDockerContainerMetric {
     timestamp: DateTime<Utc>,
@@ -22,35 +31,120 @@ pub struct InstantDockerContainerMetric {
 pub struct InstantDockerContainerMetricEntry {
     name: String,
     state: String,
+    info: DockerContainerInfo,
+    usage: Option<InstantDockerContainerUsage> // only available for running containers
+}
 
+#[derive(Debug, Clone)]
+pub struct InstantDockerContainerUsage {
     cpu_usage: u64,
     system_cpu_usage: u64,
+    online_cpus: u32,
+    cpu_throttled_periods: u64,
+    cpu_throttled_time: u64,
 
     memory_usage: u64,
-    memory_cache: u64,
+    memory_cache: u64, // inactive page cache, which can be reclaimed under memory pressure
+    memory_limit: u64,
 
     network_tx: u64,
-    network_rx: u64
+    network_rx: u64,
+    networks: HashMap<String, NetworkStat>, // by interface
+    network_names: HashMap<String, String>, // docker network by interface, if known
+
+    block_read: u64,
+    block_write: u64,
+    block_read_ops: u64,
+    block_write_ops: u64,
+
+    pids: u64
 }
 
 #[derive(Debug, Clone, Serialize)]
 pub struct DockerContainerMetric {
     timestamp: DateTime<Utc>,
-    stat: Vec<DockerContainerMetricEntry>
+    stat: Vec<DockerContainerMetricEntry>,
+    transitions: Vec<DockerContainerTransition>
 }
 
 #[derive(Debug, Clone, Serialize)]
 pub struct DockerContainerMetricEntry {
     name: String,
     state: String,
+    #[serde(flatten)]
+    info: DockerContainerInfo,
+    #[serde(flatten)]
+    usage: Option<DockerContainerUsageMetric>
+}
 
-    cpu_usage: f64,
+#[derive(Debug, Clone, Serialize)]
+pub struct DockerContainerUsageMetric {
+    cpu_usage: f64, // cores
+    cpu_throttled_periods: f64,
+    cpu_throttled_time: f64, // seconds throttled per second
 
     memory_usage: u64,
     memory_cache: u64,
+    memory_limit: u64,
+    memory_percent: f64,
 
     network_tx: f64,
     network_rx: f64,
+    networks: Vec<DockerContainerNetworkMetric>,
+
+    block_read: f64,
+    block_write: f64,
+    block_read_ops: f64,
+    block_write_ops: f64,
+
+    pids: u64
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DockerContainerNetworkMetric {
+    interface: String,
+    network: Option<String>,
+
+    rx_bytes: f64,
+    rx_packets: f64,
+    rx_errors: f64,
+    rx_dropped: f64,
+
+    tx_bytes: f64,
+    tx_packets: f64,
+    tx_errors: f64,
+    tx_dropped: f64
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DockerContainerInfo {
+    image: String,
+    compose_project: Option<String>,
+    compose_service: Option<String>,
+    health: Option<String>,
+    restart_count: u32,
+    exit_code: i32,
+    created_at: Option<DateTime<Utc>>,
+    started_at: Option<DateTime<Utc>>
+}
+
+// Container details from inspect, which only change when the container starts, stops or changes its health status.
+// Networks connected to a running container are picked up once it restarts.
+#[derive(Debug, Clone)]
+struct CachedContainerDetails {
+    name: String,
+    state: String, // at the time of inspect
+    info: DockerContainerInfo,
+    pid: u32,
+    networks: HashMap<String, String> // network name by mac address
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DockerContainerTransition {
+    name: String,
+    previous_state: String,
+    state: String,
+    exit_code: i32
 }
 
 custom_error!{pub DockerMetricError
@@ -61,44 +155,69 @@ custom_error!{pub DockerMetricError
 impl Metric for InstantDockerContainerMetric {
 }
 
+// Latest stats of running containers, kept up to date by one stats stream per container.
+#[derive(Clone, Default)]
+struct StatsStreams {
+    latest: Arc<Mutex<HashMap<String, ContainerStats>>>,
+    subscribed: Arc<Mutex<HashSet<String>>>
+}
+
 pub struct DockerMetricCollector {
+    client: DockerClient,
+    concurrency: usize,
+    stats_streams: Option<StatsStreams>,
+    changes: ContainerChanges,
+    details: HashMap<String, CachedContainerDetails>, // by container id
     previous: Option<InstantDockerContainerMetric>,
     metric: Option<DockerContainerMetric>
 }
 
 impl DockerMetricCollector {
 
-    pub fn new() -> Self {
+    pub fn new(client: DockerClient, changes: ContainerChanges) -> Self {
         DockerMetricCollector {
+            client,
+            concurrency: get_docker_stats_concurrency(),
+            stats_streams: if is_docker_stats_stream_enabled() { Some(StatsStreams::default()) } else { None },
+            changes,
+            details: HashMap::new(),
             previous: None,
             metric: None
         }
     }
 
-    async fn collect_metric(&self) -> Result<Box<InstantDockerContainerMetric>, MetricCollectionError> {
+    async fn collect_metric(&mut self) -> Result<Box<InstantDockerContainerMetric>, MetricCollectionError> {
         let timestamp = Utc::now();
-
-        let stat: Vec<InstantDockerContainerMetricEntry> = join_all(containers().await?.into_iter()
-            .map(|v| stats(v.id.clone()).map(|s| (v, s)))
-        ).await.into_iter().filter_map(|v| match v.1 {
-            Ok(stats) => Some((v.0, stats)),
-            Err(err) => {
-                warn!("failed to get container stats ({}): {}", v.0.id, err);
-                None
+        let changed = self.changes.take();
+        let containers = self.client.containers().await?;
+
+        let ids: HashSet<&String> = containers.iter().map(|v| &v.id).collect();
+        self.details.retain(|id, _| ids.contains(id));
+
+        // details are inspected again for new containers and for those which changed since they were cached,
+        // state is also compared in case events were missed (e.g. while the events stream reconnects)
+        let details = &self.details;
+        let cached = |container: &Container| details.get(&container.id)
+            .filter(|v| v.state == container.state && !changed.contains(&container.id))
+            .cloned();
+
+        // each stats request blocks for a second or two inside docker, so only a few are sent at once
+        let entries: Vec<(Container, Result<(InstantDockerContainerMetricEntry, CachedContainerDetails), DockerClientError>)> = stream::iter(containers.clone())
+            .map(|v| collect_container_entry(&self.client, self.stats_streams.as_ref(), v.clone(), cached(&v)).map(|s| (v, s)))
+            .buffer_unordered(self.concurrency)
+            .collect().await;
+
+        let mut stat = Vec::new();
+        for (container, entry) in entries {
+            match entry {
+                Ok((entry, details)) => {
+                    self.details.insert(container.id, details);
+                    stat.push(entry);
+                },
+                // only this container is skipped, e.g. it was removed after containers were listed
+                Err(err) => warn!("failed to get container stats ({}): {}", container.id, err)
             }
-        }).map(|v: (Container, ContainerStats)| InstantDockerContainerMetricEntry {
-            name: v.1.name[1..].to_string(),
-            state: v.0.state,
-
-            cpu_usage: (v.1.cpu_stats.cpu_usage.total_usage / 1000) as u64,
-            system_cpu_usage: (v.1.cpu_stats.system_cpu_usage / 1_000_000) as u64,
-
-            memory_usage: v.1.memory_stats.usage,
-            memory_cache: v.1.memory_stats.stats.cache,
-
-            network_tx: v.1.networks.iter().map(|v| v.1.tx_bytes).fold(0, |a, b| a + b),
-            network_rx: v.1.networks.iter().map(|v| v.1.rx_bytes).fold(0, |a, b| a + b)
-        }).collect();
+        }
 
         Ok(Box::new(InstantDockerContainerMetric { timestamp, stat }))
     }
@@ -128,7 +247,10 @@ impl MetricCollector for DockerMetricCollector {
             let futures = metric.clone().stat.into_iter()
                 .map(|entry| save_metric_entry(&mut database, hostname, &timestamp, entry));
 
-            try_join_all(futures).await?;
+            let transition_futures = metric.clone().transitions.into_iter()
+                .map(|entry| save_transition_entry(&database, hostname, &timestamp, entry));
+
+            try_join(try_join_all(futures), try_join_all(transition_futures)).await?;
         }
 
         Ok(())
@@ -146,13 +268,211 @@ impl MetricCollector for DockerMetricCollector {
     async fn cleanup(&self, mut database: &Database) -> Result<(), MetricCleanupError> {
         let min_timestamp = Utc::now() - get_max_metrics_age();
 
-        sqlx::query!("delete from metric_docker_containers where timestamp < $1 returning 1 as result", min_timestamp)
+        try_join(
+            sqlx::query!("delete from metric_docker_containers where timestamp < $1 returning 1 as result", min_timestamp)
+                .fetch_one(&mut database.clone()),
+            sqlx::query!("delete from metric_docker_container_transitions where timestamp < $1 returning 1 as result", min_timestamp)
+                .fetch_one(&mut database.clone())
+        ).await?;
+
+        sqlx::query!("delete from metric_docker_container_networks where timestamp < $1 returning 1 as result", min_timestamp)
             .fetch_one(&mut database).await?;
 
         Ok(())
     }
 }
 
+impl StatsStreams {
+
+    fn subscribe(&self, client: &DockerClient, container_id: &str) {
+        let is_new = self.subscribed.lock()
+            .map(|mut subscribed| subscribed.insert(container_id.to_string()))
+            .unwrap_or(false);
+
+        if !is_new {
+            return;
+        }
+
+        let streams = self.clone();
+        let client = client.clone();
+        let container_id = container_id.to_string();
+
+        tokio::spawn(async move {
+            let latest = streams.latest.clone();
+            let result = client.stats_stream(container_id.clone(), |stats| {
+                if let Ok(mut latest) = latest.lock() {
+                    latest.insert(container_id.clone(), stats);
+                }
+            }).await;
+
+            if let Err(err) = result {
+                warn!("container stats stream failed ({}): {}", container_id, err);
+            }
+
+            // stream ends when container stops, it will be subscribed again if it starts
+            if let Ok(mut latest) = streams.latest.lock() {
+                latest.remove(&container_id);
+            }
+            if let Ok(mut subscribed) = streams.subscribed.lock() {
+                subscribed.remove(&container_id);
+            }
+        });
+    }
+
+    fn latest(&self, container_id: &str) -> Option<ContainerStats> {
+        self.latest.lock().ok()?.get(container_id).cloned()
+    }
+}
+
+fn get_docker_stats_concurrency() -> usize {
+    env::var("DOCKER_STATS_CONCURRENCY").ok()
+        .and_then(|v| v.parse::<usize>().ok())
+        .filter(|v| *v > 0)
+        .unwrap_or(DEFAULT_DOCKER_STATS_CONCURRENCY)
+}
+
+fn is_docker_stats_stream_enabled() -> bool {
+    env::var("DOCKER_STATS_STREAM").map(|v| v == "true" || v == "1").unwrap_or(false)
+}
+
+async fn collect_container_entry(client: &DockerClient, stats_streams: Option<&StatsStreams>, container: Container, cached: Option<CachedContainerDetails>) -> Result<(InstantDockerContainerMetricEntry, CachedContainerDetails), DockerClientError> {
+    let details = match cached {
+        Some(cached) => cached,
+        None => cached_container_details(&container, &client.inspect(container.id.clone()).await?)
+    };
+
+    let stats = if container.state != "running" {
+        None
+    } else {
+        match stats_streams {
+            Some(stats_streams) => {
+                stats_streams.subscribe(client, &container.id);
+                stats_streams.latest(&container.id)
+            },
+            None => Some(client.stats(container.id.clone()).await?)
+        }
+    };
+
+    let usage = match &stats {
+        Some(stats) => Some(container_usage(stats, network_names(&details, stats).await)),
+        None => None
+    };
+
+    Ok((InstantDockerContainerMetricEntry {
+        name: details.name.clone(),
+        state: container.state,
+        info: details.info.clone(),
+        usage
+    }, details))
+}
+
+fn cached_container_details(container: &Container, details: &ContainerDetails) -> CachedContainerDetails {
+    let networks = details.network_settings.as_ref()
+        .and_then(|v| v.networks.as_ref())
+        .map(|networks| networks.iter()
+            .filter(|(_, endpoint)| !endpoint.mac_address.is_empty())
+            .map(|(name, endpoint)| (endpoint.mac_address.to_lowercase(), name.clone()))
+            .collect())
+        .unwrap_or_default();
+
+    CachedContainerDetails {
+        name: details.name.trim_start_matches('/').to_string(),
+        state: container.state.clone(),
+        info: container_info(details),
+        pid: details.state.pid,
+        networks
+    }
+}
+
+// Stats only name the interfaces, so they are matched with networks of the container by mac address. It is read from
+// sysfs of the container network namespace, which is only visible when the agent shares pid namespace with the host.
+// Interface of a container with a single network is known without it.
+async fn network_names(details: &CachedContainerDetails, stats: &ContainerStats) -> HashMap<String, String> {
+    let mut result = HashMap::new();
+
+    for interface in stats.networks.keys() {
+        let mac = if details.pid > 0 {
+            read_to_string(format!("/proc/{}/root/sys/class/net/{}/address", details.pid, interface)).await.ok()
+        } else {
+            None
+        };
+
+        let network = mac.and_then(|v| details.networks.get(&v.trim().to_lowercase()).cloned())
+            .or_else(|| match details.networks.len() {
+                1 => details.networks.values().next().cloned(),
+                _ => None
+            });
+
+        if let Some(network) = network {
+            result.insert(interface.clone(), network);
+        }
+    }
+
+    result
+}
+
+fn container_usage(stats: &ContainerStats, network_names: HashMap<String, String>) -> InstantDockerContainerUsage {
+    InstantDockerContainerUsage {
+        cpu_usage: stats.cpu_stats.cpu_usage.total_usage as u64,
+        system_cpu_usage: stats.cpu_stats.system_cpu_usage as u64,
+        online_cpus: stats.cpu_stats.online_cpus
+            .or_else(|| stats.cpu_stats.cpu_usage.percpu_usage.as_ref().map(|v| v.len() as u32))
+            .unwrap_or(1),
+        cpu_throttled_periods: stats.cpu_stats.throttling_data.throttled_periods,
+        cpu_throttled_time: stats.cpu_stats.throttling_data.throttled_time,
+
+        memory_usage: stats.memory_stats.usage,
+        // same as `docker stats`, which does not count inactive page cache as used memory
+        memory_cache: stats.memory_stats.stats.total_inactive_file
+            .or(stats.memory_stats.stats.inactive_file)
+            .unwrap_or(0),
+        memory_limit: stats.memory_stats.limit,
+
+        network_tx: stats.networks.iter().map(|v| v.1.tx_bytes).fold(0, |a, b| a + b),
+        network_rx: stats.networks.iter().map(|v| v.1.rx_bytes).fold(0, |a, b| a + b),
+        networks: stats.networks.clone(),
+        network_names,
+
+        block_read: blkio_total(&stats.blkio_stats.io_service_bytes_recursive, "read"),
+        block_write: blkio_total(&stats.blkio_stats.io_service_bytes_recursive, "write"),
+        block_read_ops: blkio_total(&stats.blkio_stats.io_serviced_recursive, "read"),
+        block_write_ops: blkio_total(&stats.blkio_stats.io_serviced_recursive, "write"),
+
+        pids: stats.pids_stats.current
+    }
+}
+
+fn container_info(details: &ContainerDetails) -> DockerContainerInfo {
+    let label = |name: &str| details.config.labels.as_ref().and_then(|labels| labels.get(name)).cloned();
+
+    DockerContainerInfo {
+        image: details.config.image.clone(),
+        compose_project: label("com.docker.compose.project"),
+        compose_service: label("com.docker.compose.service"),
+        health: details.state.health.as_ref().map(|v| v.status.clone()),
+        restart_count: details.restart_count,
+        exit_code: details.state.exit_code,
+        created_at: parse_docker_timestamp(&details.created),
+        started_at: parse_docker_timestamp(&details.state.started_at)
+    }
+}
+
+// docker uses "0001-01-01T00:00:00Z" for events which did not happen yet
+fn parse_docker_timestamp(timestamp: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(timestamp).ok()
+        .map(|v| v.with_timezone(&Utc))
+        .filter(|v| v.year() > 1)
+}
+
+// cgroup v1 reports ops as "Read"/"Write", cgroup v2 as "read"/"write"
+fn blkio_total(entries: &Option<Vec<BlkioStatEntry>>, op: &str) -> u64 {
+    entries.iter()
+        .flatten()
+        .filter(|entry| entry.op.eq_ignore_ascii_case(op))
+        .map(|entry| entry.value)
+        .fold(0, |a, b| a + b)
+}
+
 pub fn docker_metric_from_stats(first: &InstantDockerContainerMetric, second: &InstantDockerContainerMetric) -> DockerContainerMetric {
     let first = first.clone();
     let second = second.clone();
@@ -160,39 +480,138 @@ pub fn docker_metric_from_stats(first: &InstantDockerContainerMetric, second: &I
 
     let first_iter = first.stat.into_iter();
 
-    let stat: Vec<DockerContainerMetricEntry> = second.stat.into_iter()
+    let transitions: Vec<DockerContainerTransition> = second.stat.iter()
         .filter_map(|v| first_iter.clone()
-            .find(|item| item.name == v.name)
-            .map(|item| (item, v))
+            .find(|item| item.name == v.name && item.state != v.state)
+            .map(|item| DockerContainerTransition {
+                name: v.name.clone(),
+                previous_state: item.state,
+                state: v.state.clone(),
+                exit_code: v.info.exit_code
+            })
         )
-        .filter(|two_entries| two_entries.1.cpu_usage > two_entries.0.cpu_usage)
-        .map(|two_entries| docker_metric_entry_from_two_stats(time_diff, two_entries.0, two_entries.1))
         .collect();
 
-    DockerContainerMetric { stat, timestamp: second.timestamp }
+    // usage is only reported when there are two samples to compare, e.g. not for containers which are not running
+    // or have just started, the rest of the entry is reported anyway
+    let stat: Vec<DockerContainerMetricEntry> = second.stat.into_iter()
+        .map(|v| {
+            let previous_usage = first_iter.clone()
+                .find(|item| item.name == v.name)
+                .and_then(|item| item.usage);
+
+            let usage = match (previous_usage, &v.usage) {
+                (Some(first), Some(second)) => docker_usage_metric_from_two_stats(time_diff, first, second.clone()),
+                _ => None
+            };
+
+            DockerContainerMetricEntry {
+                name: v.name,
+                state: v.state,
+                info: v.info,
+                usage
+            }
+        })
+        .collect();
+
+    DockerContainerMetric { stat, transitions, timestamp: second.timestamp }
 }
 
-fn docker_metric_entry_from_two_stats(time_diff: Duration, first: InstantDockerContainerMetricEntry, second: InstantDockerContainerMetricEntry) -> DockerContainerMetricEntry {
-    let diff = time_diff.num_milliseconds() as f64 / 1000.0; // seconds
+fn docker_usage_metric_from_two_stats(time_diff: Duration, first: InstantDockerContainerUsage, second: InstantDockerContainerUsage) -> Option<DockerContainerUsageMetric> {
+    // counters start from zero when the container restarts, an idle container keeps the same cpu usage
+    if second.cpu_usage < first.cpu_usage || second.system_cpu_usage <= first.system_cpu_usage {
+        return None;
+    }
 
-    DockerContainerMetricEntry {
-        name: second.name,
-        state: second.state,
+    let diff = time_diff.num_milliseconds() as f64 / 1000.0; // seconds
 
-        cpu_usage: ((second.cpu_usage - first.cpu_usage) as f64 / (second.system_cpu_usage - first.system_cpu_usage) as f64) / diff,
+    Some(DockerContainerUsageMetric {
+        // system cpu usage is the sum over all host cpus, so the share is scaled by cpu count to get cores
+        cpu_usage: (second.cpu_usage - first.cpu_usage) as f64 / (second.system_cpu_usage - first.system_cpu_usage) as f64 * second.online_cpus as f64,
+        cpu_throttled_periods: second.cpu_throttled_periods.saturating_sub(first.cpu_throttled_periods) as f64 / diff,
+        cpu_throttled_time: second.cpu_throttled_time.saturating_sub(first.cpu_throttled_time) as f64 / 1_000_000_000.0 / diff,
 
         memory_usage: second.memory_usage,
         memory_cache: second.memory_cache,
+        memory_limit: second.memory_limit,
+        memory_percent: if second.memory_limit > 0 {
+            second.memory_usage.saturating_sub(second.memory_cache) as f64 / second.memory_limit as f64 * 100.0
+        } else {
+            0.0
+        },
+
+        network_tx: second.network_tx.saturating_sub(first.network_tx) as f64 / diff,
+        network_rx: second.network_rx.saturating_sub(first.network_rx) as f64 / diff,
+        networks: docker_network_metric_from_two_stats(diff, &first.networks, &second),
+
+        block_read: second.block_read.saturating_sub(first.block_read) as f64 / diff,
+        block_write: second.block_write.saturating_sub(first.block_write) as f64 / diff,
+        block_read_ops: second.block_read_ops.saturating_sub(first.block_read_ops) as f64 / diff,
+        block_write_ops: second.block_write_ops.saturating_sub(first.block_write_ops) as f64 / diff,
+
+        pids: second.pids
+    })
+}
 
-        network_tx: (second.network_tx - first.network_tx) as f64 / diff,
-        network_rx: (second.network_rx - first.network_rx) as f64 / diff
-    }
+// interfaces which appeared since the previous sample (e.g. container connected to a network) are reported starting from the next one
+fn docker_network_metric_from_two_stats(diff: f64, first: &HashMap<String, NetworkStat>, second_usage: &InstantDockerContainerUsage) -> Vec<DockerContainerNetworkMetric> {
+    second_usage.networks.iter()
+        .filter_map(|(interface, second)| {
+            let first = first.get(interface)?;
+
+            Some(DockerContainerNetworkMetric {
+                interface: interface.clone(),
+                network: second_usage.network_names.get(interface).cloned(),
+
+                rx_bytes: second.rx_bytes.saturating_sub(first.rx_bytes) as f64 / diff,
+                rx_packets: second.rx_packets.saturating_sub(first.rx_packets) as f64 / diff,
+                rx_errors: second.rx_errors.saturating_sub(first.rx_errors) as f64 / diff,
+                rx_dropped: second.rx_dropped.saturating_sub(first.rx_dropped) as f64 / diff,
+
+                tx_bytes: second.tx_bytes.saturating_sub(first.tx_bytes) as f64 / diff,
+                tx_packets: second.tx_packets.saturating_sub(first.tx_packets) as f64 / diff,
+                tx_errors: second.tx_errors.saturating_sub(first.tx_errors) as f64 / diff,
+                tx_dropped: second.tx_dropped.saturating_sub(first.tx_dropped) as f64 / diff
+            })
+        })
+        .collect()
 }
 
 async fn save_metric_entry(mut database: &Database, hostname: &str, timestamp: &DateTime<Utc>, entry: DockerContainerMetricEntry) -> Result<(), MetricSaveError> {
+    let usage = entry.usage.as_ref();
+
+    sqlx::query!(
+        "insert into metric_docker_containers (hostname, timestamp, name, state, image, compose_project, compose_service, health, restart_count, exit_code, created_at, started_at, cpu_usage, cpu_throttled_periods, cpu_throttled_time, memory_usage, memory_cache, memory_limit, memory_percent, network_tx, network_rx, block_read, block_write, block_read_ops, block_write_ops, pids) values ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18, $19, $20, $21, $22, $23, $24, $25, $26) returning name",
+        hostname.to_string(), *timestamp, entry.name, entry.state, entry.info.image, entry.info.compose_project, entry.info.compose_service,
+        entry.info.health, entry.info.restart_count as i32, entry.info.exit_code, entry.info.created_at, entry.info.started_at,
+        usage.map(|v| v.cpu_usage), usage.map(|v| v.cpu_throttled_periods), usage.map(|v| v.cpu_throttled_time),
+        usage.map(|v| v.memory_usage as i64), usage.map(|v| v.memory_cache as i64), usage.map(|v| v.memory_limit as i64),
+        usage.map(|v| v.memory_percent), usage.map(|v| v.network_tx), usage.map(|v| v.network_rx),
+        usage.map(|v| v.block_read), usage.map(|v| v.block_write), usage.map(|v| v.block_read_ops), usage.map(|v| v.block_write_ops),
+        usage.map(|v| v.pids as i32)
+    ).fetch_one(&mut database).await?;
+
+    let networks = usage.map(|v| v.networks.clone()).unwrap_or_default();
+    try_join_all(networks.into_iter().map(|network| save_network_entry(&database, hostname, timestamp, &entry.name, network))).await?;
+
+    Ok(())
+}
+
+async fn save_network_entry(mut database: &Database, hostname: &str, timestamp: &DateTime<Utc>, name: &str, entry: DockerContainerNetworkMetric) -> Result<(), MetricSaveError> {
+    sqlx::query!(
+        "insert into metric_docker_container_networks (hostname, timestamp, name, interface, network, rx_bytes, rx_packets, rx_errors, rx_dropped, tx_bytes, tx_packets, tx_errors, tx_dropped) values ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13) returning name",
+        hostname.to_string(), *timestamp, name.to_string(), entry.interface, entry.network,
+        entry.rx_bytes, entry.rx_packets, entry.rx_errors, entry.rx_dropped,
+        entry.tx_bytes, entry.tx_packets, entry.tx_errors, entry.tx_dropped
+    ).fetch_one(&mut database).await?;
+
+    Ok(())
+}
+
+async fn save_transition_entry(mut database: &Database, hostname: &str, timestamp: &DateTime<Utc>, entry: DockerContainerTransition) -> Result<(), MetricSaveError> {
     sqlx::query!(
-        "insert into metric_docker_containers (hostname, timestamp, name, state, cpu_usage, memory_usage, memory_cache, network_tx, network_rx) values ($1, $2, $3, $4, $5, $6, $7, $8, $9) returning name",
-        hostname.to_string(), *timestamp, entry.name, entry.state, entry.cpu_usage, entry.memory_usage as i64, entry.memory_cache as i64, entry.network_tx, entry.network_rx
+        "insert into metric_docker_container_transitions (hostname, timestamp, name, previous_state, state, exit_code) values ($1, $2, $3, $4, $5, $6) returning name",
+        hostname.to_string(), *timestamp, entry.name, entry.previous_state, entry.state, entry.exit_code
     ).fetch_one(&mut database).await?;
 
     Ok(())
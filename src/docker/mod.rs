@@ -1,2 +1,4 @@
 pub mod client;
-pub mod metric;
\ No newline at end of file
+pub mod disk;
+pub mod events;
+pub mod metric;
@@ -0,0 +1,129 @@
+use std::collections::HashMap;
+
+use async_std::fs::read_to_string;
+use chrono::{Utc, DateTime};
+use async_trait::async_trait;
+use serde::Serialize;
+
+use crate::database::Database;
+use crate::process::procfs::boot_time;
+use crate::types::{Metric, MetricCollectionError, MetricSaveError, MetricCleanupError, MetricCollector, MetricEncodingError};
+
+const AGENT_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+#[derive(Debug, Clone, Serialize)]
+pub struct HostInventory {
+    timestamp: DateTime<Utc>,
+    kernel: String,
+    os_release: Option<String>,
+    cpu_model: Option<String>,
+    cpu_cores: u32,
+    memory_total: i64,
+    boot_time: DateTime<Utc>,
+    uptime: f64,
+    agent_version: String
+}
+
+impl Metric for HostInventory {
+}
+
+pub struct InventoryMetricCollector {
+    metric: Option<HostInventory>
+}
+
+impl InventoryMetricCollector {
+
+    pub fn new() -> Self {
+        InventoryMetricCollector {
+            metric: None
+        }
+    }
+}
+
+#[async_trait]
+impl MetricCollector for InventoryMetricCollector {
+
+    fn key(&self) -> String {
+        "inventory".to_string()
+    }
+
+    async fn collect(&mut self) -> Result<(), MetricCollectionError> {
+        let timestamp = Utc::now();
+
+        let kernel = read_to_string("/proc/sys/kernel/osrelease").await?.trim().to_string();
+        let os_release = read_to_string("/etc/os-release").await.ok()
+            .and_then(|v| parse_os_release(&v).remove("PRETTY_NAME"));
+
+        let cpuinfo = read_to_string("/proc/cpuinfo").await?;
+        let cpu_model = cpuinfo.lines()
+            .find(|line| line.starts_with("model name"))
+            .and_then(|line| line.splitn(2, ':').nth(1))
+            .map(|v| v.trim().to_string());
+        let cpu_cores = cpuinfo.lines().filter(|line| line.starts_with("processor")).count() as u32;
+
+        let memory_total = read_to_string("/proc/meminfo").await?.lines()
+            .find(|line| line.starts_with("MemTotal:"))?
+            .split_whitespace().nth(1)?
+            .parse()?;
+
+        let uptime = read_to_string("/proc/uptime").await?
+            .split_whitespace().next()?
+            .parse()?;
+
+        self.metric = Some(HostInventory {
+            timestamp,
+            kernel,
+            os_release,
+            cpu_model,
+            cpu_cores,
+            memory_total,
+            boot_time: boot_time().await?,
+            uptime,
+            agent_version: AGENT_VERSION.to_string()
+        });
+
+        Ok(())
+    }
+
+    async fn save(&self, mut database: &Database, hostname: &str) -> Result<(), MetricSaveError> {
+        if let Some(metric) = &self.metric {
+            sqlx::query!(
+                r"insert into hosts (hostname, kernel, os_release, cpu_model, cpu_cores, memory_total, boot_time, uptime, agent_version, last_seen)
+values ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+on conflict (hostname) do update set kernel = excluded.kernel, os_release = excluded.os_release,
+    cpu_model = excluded.cpu_model, cpu_cores = excluded.cpu_cores, memory_total = excluded.memory_total,
+    boot_time = excluded.boot_time, uptime = excluded.uptime, agent_version = excluded.agent_version,
+    last_seen = excluded.last_seen
+returning hostname",
+                hostname.to_string(), metric.kernel.clone(), metric.os_release.clone(), metric.cpu_model.clone(),
+                metric.cpu_cores as i32, metric.memory_total, metric.boot_time, metric.uptime,
+                metric.agent_version.clone(), metric.timestamp
+            ).fetch_one(&mut database).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn encode(&self) -> Result<String, MetricEncodingError> {
+        if let Some(metric) = &self.metric {
+            let v = serde_json::to_string(metric)?;
+            return Ok(v);
+        }
+
+        Err(MetricEncodingError::NoRecord)
+    }
+
+    async fn cleanup(&self, _database: &Database) -> Result<(), MetricCleanupError> {
+        // hosts are never removed: last_seen is what shows that a host stopped reporting
+        Ok(())
+    }
+}
+
+fn parse_os_release(os_release: &str) -> HashMap<String, String> {
+    os_release.lines()
+        .filter_map(|line| {
+            let mut spl = line.splitn(2, '=');
+            Some((spl.next()?.trim().to_string(), spl.next()?.trim().trim_matches('"').to_string()))
+        })
+        .collect()
+}
@@ -0,0 +1,177 @@
+use async_std::fs::read_to_string;
+use chrono::{Utc, DateTime};
+use futures::future::{try_join_all, try_join};
+use async_trait::async_trait;
+use log::warn;
+use serde::Serialize;
+
+use crate::database::Database;
+use crate::config::get_max_metrics_age;
+use crate::process::procfs;
+use crate::process::watch::{WatchedProcess, get_watched_processes, find_watched_processes};
+use crate::types::{Metric, MetricCollectionError, MetricSaveError, MetricCleanupError, MetricCollector, MetricEncodingError};
+
+#[derive(Debug, Clone, Serialize)]
+pub struct KernelLimitsMetric {
+    timestamp: DateTime<Utc>,
+
+    files_allocated: u64,
+    files_max: u64,
+
+    inodes: u64,
+    inodes_free: u64,
+
+    pid_max: u64,
+    processes: u32,
+    threads: u32,
+
+    process_fds: Vec<ProcessFdMetricEntry>
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ProcessFdMetricEntry {
+    name: String,
+    pid: u32,
+    open_fds: u32,
+    max_fds: Option<u64>
+}
+
+impl Metric for KernelLimitsMetric {
+}
+
+pub struct KernelLimitsMetricCollector {
+    watched: Vec<WatchedProcess>,
+    metric: Option<KernelLimitsMetric>
+}
+
+impl KernelLimitsMetricCollector {
+
+    pub fn new() -> Self {
+        KernelLimitsMetricCollector {
+            watched: get_watched_processes(),
+            metric: None
+        }
+    }
+
+    async fn collect_process_fds(&self) -> Result<Vec<ProcessFdMetricEntry>, MetricCollectionError> {
+        let mut result = Vec::new();
+
+        for (watched, instances) in find_watched_processes(&self.watched).await? {
+            for instance in instances {
+                // fds of other users' processes are only readable when running as root
+                match process_fd_entry(&watched.name, instance.pid).await {
+                    Ok(entry) => result.push(entry),
+                    Err(err) => warn!("failed to get open files for {} ({}): {}", watched.name, instance.pid, err)
+                }
+            }
+        }
+
+        Ok(result)
+    }
+}
+
+#[async_trait]
+impl MetricCollector for KernelLimitsMetricCollector {
+
+    fn key(&self) -> String {
+        "limits".to_string()
+    }
+
+    async fn collect(&mut self) -> Result<(), MetricCollectionError> {
+        let timestamp = Utc::now();
+
+        // allocated, unused (always 0 since linux 2.6) and max file handles
+        let file_nr = read_to_string("/proc/sys/fs/file-nr").await?;
+        let mut file_nr = file_nr.split_whitespace();
+        let files_allocated = file_nr.next()?.parse()?;
+        let files_max = file_nr.nth(1)?.parse()?;
+
+        let inode_nr = read_to_string("/proc/sys/fs/inode-nr").await?;
+        let mut inode_nr = inode_nr.split_whitespace();
+        let inodes = inode_nr.next()?.parse()?;
+        let inodes_free = inode_nr.next()?.parse()?;
+
+        let pid_max = read_to_string("/proc/sys/kernel/pid_max").await?.trim().parse()?;
+
+        // the fourth field of loadavg is "<runnable>/<total>" kernel scheduling entities, i.e. threads
+        let threads = read_to_string("/proc/loadavg").await?
+            .split_whitespace().nth(3)?
+            .split('/').nth(1)?
+            .parse()?;
+
+        self.metric = Some(KernelLimitsMetric {
+            timestamp,
+            files_allocated,
+            files_max,
+            inodes,
+            inodes_free,
+            pid_max,
+            processes: procfs::pids().await?.len() as u32,
+            threads,
+            process_fds: self.collect_process_fds().await?
+        });
+
+        Ok(())
+    }
+
+    async fn save(&self, mut database: &Database, hostname: &str) -> Result<(), MetricSaveError> {
+        if let Some(metric) = &self.metric {
+            let timestamp = metric.timestamp.clone();
+
+            sqlx::query!(
+                "insert into metric_kernel_limits (hostname, timestamp, files_allocated, files_max, inodes, inodes_free, pid_max, processes, threads) values ($1, $2, $3, $4, $5, $6, $7, $8, $9) returning hostname",
+                hostname.to_string(), timestamp, metric.files_allocated as i64, metric.files_max as i64,
+                metric.inodes as i64, metric.inodes_free as i64, metric.pid_max as i64,
+                metric.processes as i32, metric.threads as i32
+            ).fetch_one(&mut database).await?;
+
+            let futures = metric.clone().process_fds.into_iter()
+                .map(|entry| save_process_fd_entry(&database, hostname, &timestamp, entry));
+
+            try_join_all(futures).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn encode(&self) -> Result<String, MetricEncodingError> {
+        if let Some(metric) = &self.metric {
+            let v = serde_json::to_string(metric)?;
+            return Ok(v);
+        }
+
+        Err(MetricEncodingError::NoRecord)
+    }
+
+    async fn cleanup(&self, mut database: &Database) -> Result<(), MetricCleanupError> {
+        let min_timestamp = Utc::now() - get_max_metrics_age();
+
+        try_join(
+            sqlx::query!("delete from metric_kernel_limits where timestamp < $1 returning 1 as result", min_timestamp)
+                .fetch_one(&mut database.clone()),
+            sqlx::query!("delete from metric_process_fds where timestamp < $1 returning 1 as result", min_timestamp)
+                .fetch_one(&mut database)
+        ).await?;
+
+        Ok(())
+    }
+}
+
+async fn process_fd_entry(name: &str, pid: u32) -> Result<ProcessFdMetricEntry, MetricCollectionError> {
+    Ok(ProcessFdMetricEntry {
+        name: name.to_string(),
+        pid,
+        open_fds: procfs::open_fds(pid).await?,
+        max_fds: procfs::max_open_files(pid).await?
+    })
+}
+
+async fn save_process_fd_entry(mut database: &Database, hostname: &str, timestamp: &DateTime<Utc>, entry: ProcessFdMetricEntry) -> Result<(), MetricSaveError> {
+    sqlx::query!(
+        "insert into metric_process_fds (hostname, timestamp, name, pid, open_fds, max_fds) values ($1, $2, $3, $4, $5, $6) returning hostname",
+        hostname.to_string(), *timestamp, entry.name, entry.pid as i32, entry.open_fds as i32,
+        entry.max_fds.map(|v| v as i64)
+    ).fetch_one(&mut database).await?;
+
+    Ok(())
+}
@@ -9,18 +9,25 @@ extern crate serde_derive;
 extern crate custom_error;
 extern crate chrono;
 
+mod cgroup;
 mod config;
+mod conntrack;
 mod cpu;
 mod database;
 mod docker;
 mod fs;
 mod hostname;
+mod inventory;
 mod io;
+mod limits;
 mod load_avg;
 mod memory;
+mod mysql;
 mod network;
 mod nginx;
 mod postgres;
+mod process;
+mod redis;
 mod types;
 
 use std::time::Duration;
@@ -40,8 +47,27 @@ use crate::io::IOMetricCollector;
 use crate::fs::FilesystemMetricCollector;
 use crate::network::NetworkMetricCollector;
 use crate::docker::metric::DockerMetricCollector;
+use crate::docker::events::DockerEventsCollector;
+use crate::docker::disk::DockerDiskUsageCollector;
+use crate::docker::client::DockerClient;
 use crate::nginx::NginxMetricCollector;
-use crate::postgres::PostgresMetricCollector;
+use crate::redis::RedisMetricCollector;
+use crate::mysql::MysqlMetricCollector;
+use crate::postgres::activity::PostgresActivityMetricCollector;
+use crate::postgres::bgwriter::PostgresBgwriterMetricCollector;
+use crate::postgres::indexes::PostgresIndexesMetricCollector;
+use crate::postgres::locks::PostgresLocksMetricCollector;
+use crate::postgres::metric::PostgresMetricCollector;
+use crate::postgres::replication::PostgresReplicationMetricCollector;
+use crate::postgres::statements::PostgresStatementsMetricCollector;
+use crate::postgres::target::get_postgres_targets;
+use crate::conntrack::ConntrackMetricCollector;
+use crate::process::metric::ProcessMetricCollector;
+use crate::process::watch::ProcessWatchMetricCollector;
+use crate::inventory::InventoryMetricCollector;
+use crate::limits::KernelLimitsMetricCollector;
+use crate::cgroup::container::CgroupContainerMetricCollector;
+use crate::cgroup::systemd::SystemdUnitMetricCollector;
 use crate::types::MetricCollector;
 use futures::FutureExt;
 
@@ -110,12 +136,54 @@ fn get_collectors(database: &Database) -> Vec<Box<dyn MetricCollector>> {
     let mut memory_collector = MemoryMetricCollector::new();
     let mut network_collector = NetworkMetricCollector::new();
     let mut nginx_collector = NginxMetricCollector::new();
-    let mut postgres_collector = PostgresMetricCollector::new(database.clone());
-    let mut docker_collector = DockerMetricCollector::new();
+    let mut redis_collector = RedisMetricCollector::new();
+    let mut mysql_collector = MysqlMetricCollector::new();
+    let mut conntrack_collector = ConntrackMetricCollector::new();
+    let mut process_collector = ProcessMetricCollector::new();
+    let mut process_watch_collector = ProcessWatchMetricCollector::new();
+    let mut inventory_collector = InventoryMetricCollector::new();
+    let mut limits_collector = KernelLimitsMetricCollector::new();
+    let mut systemd_collector = SystemdUnitMetricCollector::new();
+
+    // invalid docker configuration only disables docker metrics
+    let docker_client = match DockerClient::from_env() {
+        Ok(v) => Some(v),
+        Err(err) => {
+            warn!("docker metrics are disabled: {}", err);
+            None
+        }
+    };
+    let mut cgroup_container_collector = CgroupContainerMetricCollector::new(docker_client.clone());
 
-    vec![
+    let mut collectors: Vec<Box<dyn MetricCollector>> = vec![
         Box::new(cpu_collector), Box::new(fs_collector), Box::new(io_collector), Box::new(la_collector),
-        Box::new(memory_collector), Box::new(network_collector), Box::new(nginx_collector),
-        Box::new(postgres_collector), Box::new(docker_collector)
-    ]
+        Box::new(memory_collector), Box::new(network_collector), Box::new(nginx_collector), Box::new(redis_collector),
+        Box::new(mysql_collector), Box::new(conntrack_collector),
+        Box::new(process_collector), Box::new(process_watch_collector),
+        Box::new(inventory_collector), Box::new(limits_collector),
+        Box::new(cgroup_container_collector), Box::new(systemd_collector)
+    ];
+
+    if let Some(docker_client) = docker_client {
+        let docker_events_collector = DockerEventsCollector::new(docker_client.clone());
+        let docker_collector = DockerMetricCollector::new(docker_client.clone(), docker_events_collector.changes());
+        let docker_disk_collector = DockerDiskUsageCollector::new(docker_client);
+
+        collectors.push(Box::new(docker_collector));
+        collectors.push(Box::new(docker_events_collector));
+        collectors.push(Box::new(docker_disk_collector));
+    }
+
+    // each postgres server is monitored by its own collector
+    for target in get_postgres_targets(database) {
+        collectors.push(Box::new(PostgresMetricCollector::new(target.clone())));
+        collectors.push(Box::new(PostgresActivityMetricCollector::new(target.clone())));
+        collectors.push(Box::new(PostgresReplicationMetricCollector::new(target.clone())));
+        collectors.push(Box::new(PostgresStatementsMetricCollector::new(target.clone())));
+        collectors.push(Box::new(PostgresBgwriterMetricCollector::new(target.clone())));
+        collectors.push(Box::new(PostgresLocksMetricCollector::new(target.clone())));
+        collectors.push(Box::new(PostgresIndexesMetricCollector::new(target)));
+    }
+
+    collectors
 }
@@ -0,0 +1,170 @@
+use chrono::{DateTime, Utc};
+use futures::future::{try_join_all, try_join};
+use futures::TryStreamExt;
+use async_trait::async_trait;
+use serde::Serialize;
+
+use crate::database::Database;
+use crate::config::get_max_metrics_age;
+use crate::postgres::target::PostgresTarget;
+use crate::types::{Metric, MetricCollectionError, MetricSaveError, MetricCleanupError, MetricCollector, MetricEncodingError};
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PostgresActivityMetric {
+    timestamp: DateTime<Utc>,
+    target: String,
+
+    connections: i64,
+    max_connections: i32,
+    connections_percent: f64,
+
+    longest_query: f64, // seconds, of queries which are still running
+    longest_transaction: f64, // seconds
+    waiting_on_locks: i64,
+
+    connection_stat: Vec<PostgresConnectionMetricEntry>
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PostgresConnectionMetricEntry {
+    database: String,
+    user: String,
+    application: String,
+    state: String, // active, idle, idle in transaction, ...
+    connections: i64
+}
+
+impl Metric for PostgresActivityMetric {
+}
+
+pub struct PostgresActivityMetricCollector {
+    target: PostgresTarget,
+    metric: Option<PostgresActivityMetric>
+}
+
+impl PostgresActivityMetricCollector {
+
+    pub fn new(target: PostgresTarget) -> Self {
+        PostgresActivityMetricCollector {
+            target,
+            metric: None
+        }
+    }
+
+    async fn collect_metric(&self) -> Result<Box<PostgresActivityMetric>, MetricCollectionError> {
+        let database = self.target.database().await?;
+        let mut database = &database;
+
+        let timestamp = Utc::now();
+
+        // only client connections are counted (not background workers) and the agent's own connection is skipped
+        let summary = sqlx::query!(r"
+SELECT count(*) AS connections,
+       cast(current_setting('max_connections') as int) AS max_connections,
+       cast(coalesce(extract(epoch from max(now() - query_start) filter (where state = 'active')), 0) as double precision) AS longest_query,
+       cast(coalesce(extract(epoch from max(now() - xact_start)), 0) as double precision) AS longest_transaction,
+       count(*) filter (where wait_event_type = 'Lock') AS waiting_on_locks
+  FROM pg_stat_activity
+  WHERE backend_type = 'client backend' AND pid <> pg_backend_pid();").fetch_one(&mut database).await?;
+
+        let connection_stat = sqlx::query!(r"
+SELECT cast(coalesce(datname, '') as text) AS database,
+       cast(coalesce(usename, '') as text) AS user_name,
+       coalesce(application_name, '') AS application,
+       coalesce(state, 'unknown') AS state,
+       count(*) AS connections
+  FROM pg_stat_activity
+  WHERE backend_type = 'client backend' AND pid <> pg_backend_pid()
+  GROUP BY 1, 2, 3, 4;").fetch(&mut database).map_ok(|rec| PostgresConnectionMetricEntry {
+            database: rec.database,
+            user: rec.user_name,
+            application: rec.application,
+            state: rec.state,
+            connections: rec.connections
+        }).try_collect().await?;
+
+        Ok(Box::new(PostgresActivityMetric {
+            timestamp,
+            target: self.target.name.clone(),
+
+            connections: summary.connections,
+            max_connections: summary.max_connections,
+            connections_percent: if summary.max_connections > 0 {
+                summary.connections as f64 / summary.max_connections as f64 * 100.0
+            } else {
+                0.0
+            },
+
+            longest_query: summary.longest_query,
+            longest_transaction: summary.longest_transaction,
+            waiting_on_locks: summary.waiting_on_locks,
+
+            connection_stat
+        }))
+    }
+}
+
+#[async_trait]
+impl MetricCollector for PostgresActivityMetricCollector {
+
+    fn key(&self) -> String {
+        format!("postgres_activity_{}", self.target.name)
+    }
+
+    async fn collect(&mut self) -> Result<(), MetricCollectionError> {
+        self.metric = Some(*self.collect_metric().await?);
+
+        Ok(())
+    }
+
+    async fn save(&self, mut database: &Database, hostname: &str) -> Result<(), MetricSaveError> {
+        if let Some(metric) = &self.metric {
+            let timestamp = metric.timestamp.clone();
+
+            sqlx::query!(
+                "insert into metric_postgres_activity (hostname, timestamp, target, connections, max_connections, connections_percent, longest_query, longest_transaction, waiting_on_locks) values ($1, $2, $3, $4, $5, $6, $7, $8, $9) returning hostname",
+                hostname.to_string(), timestamp, metric.target.clone(), metric.connections as i32, metric.max_connections,
+                metric.connections_percent, metric.longest_query, metric.longest_transaction, metric.waiting_on_locks as i32
+            ).fetch_one(&mut database).await?;
+
+            let futures = metric.clone().connection_stat.into_iter()
+                .map(|entry| save_connection_entry(&database, hostname, &timestamp, &metric.target, entry));
+
+            try_join_all(futures).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn encode(&self) -> Result<String, MetricEncodingError> {
+        if let Some(metric) = &self.metric {
+            let v = serde_json::to_string(metric)?;
+            return Ok(v);
+        }
+
+        Err(MetricEncodingError::NoRecord)
+    }
+
+    async fn cleanup(&self, mut database: &Database) -> Result<(), MetricCleanupError> {
+        let min_timestamp = Utc::now() - get_max_metrics_age();
+
+        try_join(
+            sqlx::query!("delete from metric_postgres_activity where timestamp < $1 returning 1 as result", min_timestamp)
+                .fetch_one(&mut database.clone()),
+            sqlx::query!("delete from metric_postgres_connections where timestamp < $1 returning 1 as result", min_timestamp)
+                .fetch_one(&mut database)
+        ).await?;
+
+        Ok(())
+    }
+}
+
+async fn save_connection_entry(mut database: &Database, hostname: &str, timestamp: &DateTime<Utc>, target: &str, entry: PostgresConnectionMetricEntry) -> Result<(), MetricSaveError> {
+    sqlx::query!(
+        "insert into metric_postgres_connections (hostname, timestamp, target, database, user_name, application, state, connections) values ($1, $2, $3, $4, $5, $6, $7, $8) returning hostname",
+        hostname.to_string(), *timestamp, target.to_string(), entry.database, entry.user, entry.application, entry.state,
+        entry.connections as i32
+    ).fetch_one(&mut database).await?;
+
+    Ok(())
+}
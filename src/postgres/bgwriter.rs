@@ -0,0 +1,202 @@
+use chrono::{DateTime, Utc};
+use async_trait::async_trait;
+use serde::Serialize;
+use sqlx::Row;
+
+use crate::database::Database;
+use crate::config::get_max_metrics_age;
+use crate::postgres::metric::counter_delta;
+use crate::postgres::target::PostgresTarget;
+use crate::types::{Metric, MetricCollectionError, MetricSaveError, MetricCleanupError, MetricCollector, MetricEncodingError};
+
+#[derive(Debug, Clone)]
+pub struct InstantPostgresBgwriterMetric {
+    timestamp: DateTime<Utc>,
+
+    checkpoints_timed: i64,
+    checkpoints_req: i64,
+    checkpoint_write_time: f64, // milliseconds
+    checkpoint_sync_time: f64,
+
+    buffers_checkpoint: i64,
+    buffers_clean: i64,
+    maxwritten_clean: i64,
+    buffers_backend: Option<i64>, // read from pg_stat_io since postgres 17
+    buffers_backend_fsync: Option<i64>,
+    buffers_alloc: i64
+}
+
+// All values are per second.
+#[derive(Debug, Clone, Serialize)]
+pub struct PostgresBgwriterMetric {
+    timestamp: DateTime<Utc>,
+    target: String,
+
+    checkpoints_timed: f64,
+    checkpoints_req: f64,
+    checkpoint_write_time: f64, // milliseconds spent per second
+    checkpoint_sync_time: f64,
+
+    buffers_checkpoint: f64,
+    buffers_clean: f64,
+    maxwritten_clean: f64, // bgwriter stopped because it wrote too many buffers
+    buffers_backend: Option<f64>, // written by backends themselves, high values mean bgwriter does not keep up
+    buffers_backend_fsync: Option<f64>,
+    buffers_alloc: f64
+}
+
+impl Metric for InstantPostgresBgwriterMetric {
+}
+
+pub struct PostgresBgwriterMetricCollector {
+    target: PostgresTarget,
+    previous: Option<InstantPostgresBgwriterMetric>,
+    metric: Option<PostgresBgwriterMetric>
+}
+
+impl PostgresBgwriterMetricCollector {
+
+    pub fn new(target: PostgresTarget) -> Self {
+        PostgresBgwriterMetricCollector {
+            target,
+            previous: None,
+            metric: None
+        }
+    }
+
+    async fn collect_metric(&self) -> Result<Box<InstantPostgresBgwriterMetric>, MetricCollectionError> {
+        let database = self.target.database().await?;
+        let mut database = &database;
+
+        let version = sqlx::query!("SELECT cast(current_setting('server_version_num') as int) AS version")
+            .fetch_one(&mut database).await?
+            .version;
+
+        let timestamp = Utc::now();
+
+        // checkpoint statistics were moved to pg_stat_checkpointer in postgres 17, and writes of backends to pg_stat_io.
+        // The view does not exist on older servers, so this query can not be checked at compile time.
+        if version >= 170000 {
+            let row = sqlx::query(r"
+SELECT c.num_timed, c.num_requested, c.write_time, c.sync_time, c.buffers_written,
+       b.buffers_clean, b.maxwritten_clean, b.buffers_alloc,
+       cast(coalesce(io.writes, 0) as bigint) AS buffers_backend,
+       cast(coalesce(io.fsyncs, 0) as bigint) AS buffers_backend_fsync
+  FROM pg_stat_checkpointer c, pg_stat_bgwriter b,
+       (SELECT sum(writes) AS writes, sum(fsyncs) AS fsyncs FROM pg_stat_io WHERE backend_type = 'client backend') io;").fetch_one(&mut database).await?;
+
+            return Ok(Box::new(InstantPostgresBgwriterMetric {
+                timestamp,
+
+                checkpoints_timed: row.try_get("num_timed")?,
+                checkpoints_req: row.try_get("num_requested")?,
+                checkpoint_write_time: row.try_get("write_time")?,
+                checkpoint_sync_time: row.try_get("sync_time")?,
+
+                buffers_checkpoint: row.try_get("buffers_written")?,
+                buffers_clean: row.try_get("buffers_clean")?,
+                maxwritten_clean: row.try_get("maxwritten_clean")?,
+                buffers_backend: Some(row.try_get("buffers_backend")?),
+                buffers_backend_fsync: Some(row.try_get("buffers_backend_fsync")?),
+                buffers_alloc: row.try_get("buffers_alloc")?
+            }));
+        }
+
+        let rec = sqlx::query!(r"
+SELECT checkpoints_timed, checkpoints_req, checkpoint_write_time, checkpoint_sync_time,
+       buffers_checkpoint, buffers_clean, maxwritten_clean, buffers_backend, buffers_backend_fsync, buffers_alloc
+  FROM pg_stat_bgwriter;").fetch_one(&mut database).await?;
+
+        Ok(Box::new(InstantPostgresBgwriterMetric {
+            timestamp,
+
+            checkpoints_timed: rec.checkpoints_timed,
+            checkpoints_req: rec.checkpoints_req,
+            checkpoint_write_time: rec.checkpoint_write_time,
+            checkpoint_sync_time: rec.checkpoint_sync_time,
+
+            buffers_checkpoint: rec.buffers_checkpoint,
+            buffers_clean: rec.buffers_clean,
+            maxwritten_clean: rec.maxwritten_clean,
+            buffers_backend: Some(rec.buffers_backend),
+            buffers_backend_fsync: Some(rec.buffers_backend_fsync),
+            buffers_alloc: rec.buffers_alloc
+        }))
+    }
+}
+
+#[async_trait]
+impl MetricCollector for PostgresBgwriterMetricCollector {
+
+    fn key(&self) -> String {
+        format!("postgres_bgwriter_{}", self.target.name)
+    }
+
+    async fn collect(&mut self) -> Result<(), MetricCollectionError> {
+        let metric = self.collect_metric().await?;
+        if let Some(prev) = &self.previous {
+            self.metric = Some(bgwriter_metric_from_two_stats(&self.target.name, prev, &metric));
+        }
+        self.previous = Some(*metric);
+
+        Ok(())
+    }
+
+    async fn save(&self, mut database: &Database, hostname: &str) -> Result<(), MetricSaveError> {
+        if let Some(metric) = &self.metric {
+            sqlx::query!(
+                "insert into metric_postgres_bgwriter (hostname, timestamp, target, checkpoints_timed, checkpoints_req, checkpoint_write_time, checkpoint_sync_time, buffers_checkpoint, buffers_clean, maxwritten_clean, buffers_backend, buffers_backend_fsync, buffers_alloc) values ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13) returning hostname",
+                hostname.to_string(), metric.timestamp, metric.target.clone(),
+                metric.checkpoints_timed, metric.checkpoints_req, metric.checkpoint_write_time, metric.checkpoint_sync_time,
+                metric.buffers_checkpoint, metric.buffers_clean, metric.maxwritten_clean,
+                metric.buffers_backend, metric.buffers_backend_fsync, metric.buffers_alloc
+            ).fetch_one(&mut database).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn encode(&self) -> Result<String, MetricEncodingError> {
+        if let Some(metric) = &self.metric {
+            let v = serde_json::to_string(metric)?;
+            return Ok(v);
+        }
+
+        Err(MetricEncodingError::NoRecord)
+    }
+
+    async fn cleanup(&self, mut database: &Database) -> Result<(), MetricCleanupError> {
+        let min_timestamp = Utc::now() - get_max_metrics_age();
+
+        sqlx::query!("delete from metric_postgres_bgwriter where timestamp < $1 returning 1 as result", min_timestamp)
+            .fetch_one(&mut database).await?;
+
+        Ok(())
+    }
+}
+
+fn bgwriter_metric_from_two_stats(target: &str, first: &InstantPostgresBgwriterMetric, second: &InstantPostgresBgwriterMetric) -> PostgresBgwriterMetric {
+    let time_diff = second.timestamp - first.timestamp;
+    let diff = time_diff.num_milliseconds() as f64 / 1000.0; // seconds
+
+    PostgresBgwriterMetric {
+        timestamp: second.timestamp,
+        target: target.to_string(),
+
+        checkpoints_timed: counter_delta(first.checkpoints_timed, second.checkpoints_timed) as f64 / diff,
+        checkpoints_req: counter_delta(first.checkpoints_req, second.checkpoints_req) as f64 / diff,
+        checkpoint_write_time: (second.checkpoint_write_time - first.checkpoint_write_time).max(0.0) / diff,
+        checkpoint_sync_time: (second.checkpoint_sync_time - first.checkpoint_sync_time).max(0.0) / diff,
+
+        buffers_checkpoint: counter_delta(first.buffers_checkpoint, second.buffers_checkpoint) as f64 / diff,
+        buffers_clean: counter_delta(first.buffers_clean, second.buffers_clean) as f64 / diff,
+        maxwritten_clean: counter_delta(first.maxwritten_clean, second.maxwritten_clean) as f64 / diff,
+        buffers_backend: optional_counter_rate(first.buffers_backend, second.buffers_backend, diff),
+        buffers_backend_fsync: optional_counter_rate(first.buffers_backend_fsync, second.buffers_backend_fsync, diff),
+        buffers_alloc: counter_delta(first.buffers_alloc, second.buffers_alloc) as f64 / diff
+    }
+}
+
+fn optional_counter_rate(first: Option<i64>, second: Option<i64>, diff: f64) -> Option<f64> {
+    Some(counter_delta(first?, second?) as f64 / diff)
+}
@@ -0,0 +1,157 @@
+use chrono::{DateTime, Utc};
+use futures::future::try_join_all;
+use futures::TryStreamExt;
+use async_trait::async_trait;
+use serde::Serialize;
+
+use crate::database::Database;
+use crate::config::{get_max_metrics_age, get_slow_metric_report_interval};
+use crate::postgres::target::PostgresTarget;
+use crate::types::{Metric, MetricCollectionError, MetricSaveError, MetricCleanupError, MetricCollector, MetricEncodingError};
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PostgresIndexesMetric {
+    timestamp: DateTime<Utc>,
+    target: String,
+    stat: Vec<IndexMetricEntry>
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct IndexMetricEntry {
+    schema: String,
+    table: String,
+    index: String,
+
+    scans: i64, // since statistics were reset
+    size: i64,
+
+    unique: bool,
+    unused: bool, // never scanned and not enforcing uniqueness
+    duplicate: bool, // same columns, operator classes, collations, expressions and predicate as another index of the table
+    invalid: bool // e.g. failed concurrent build, still updated on writes but never used
+}
+
+impl Metric for PostgresIndexesMetric {
+}
+
+pub struct PostgresIndexesMetricCollector {
+    target: PostgresTarget,
+    last_collected: Option<DateTime<Utc>>,
+    metric: Option<PostgresIndexesMetric>
+}
+
+impl PostgresIndexesMetricCollector {
+
+    pub fn new(target: PostgresTarget) -> Self {
+        PostgresIndexesMetricCollector {
+            target,
+            last_collected: None,
+            metric: None
+        }
+    }
+
+    async fn collect_metric(&self, timestamp: DateTime<Utc>) -> Result<Box<PostgresIndexesMetric>, MetricCollectionError> {
+        let database = self.target.database().await?;
+        let mut database = &database;
+
+        // only indexes of the database in the connection string are visible
+        let stat = sqlx::query!(r"
+SELECT cast(s.schemaname as text) AS schema_name, cast(s.relname as text) AS table_name, cast(s.indexrelname as text) AS index_name,
+       s.idx_scan, pg_relation_size(s.indexrelid) AS index_bytes,
+       (i.indisunique OR i.indisprimary) AS is_unique,
+       NOT i.indisvalid AS is_invalid,
+       EXISTS (
+           SELECT 1 FROM pg_index o
+             WHERE o.indexrelid <> i.indexrelid AND o.indrelid = i.indrelid AND o.indisvalid
+               AND o.indkey = i.indkey AND o.indclass = i.indclass AND o.indcollation = i.indcollation
+               AND coalesce(pg_get_expr(o.indexprs, o.indrelid), '') = coalesce(pg_get_expr(i.indexprs, i.indrelid), '')
+               AND coalesce(pg_get_expr(o.indpred, o.indrelid), '') = coalesce(pg_get_expr(i.indpred, i.indrelid), '')
+       ) AS is_duplicate
+  FROM pg_stat_user_indexes s
+  JOIN pg_index i ON i.indexrelid = s.indexrelid;").fetch(&mut database).map_ok(|rec| IndexMetricEntry {
+            schema: rec.schema_name,
+            table: rec.table_name,
+            index: rec.index_name,
+            scans: rec.idx_scan,
+            size: rec.index_bytes,
+            unique: rec.is_unique,
+            unused: rec.idx_scan == 0 && !rec.is_unique,
+            duplicate: rec.is_duplicate,
+            invalid: rec.is_invalid
+        }).try_collect().await?;
+
+        Ok(Box::new(PostgresIndexesMetric {
+            timestamp,
+            target: self.target.name.clone(),
+            stat
+        }))
+    }
+}
+
+#[async_trait]
+impl MetricCollector for PostgresIndexesMetricCollector {
+
+    fn key(&self) -> String {
+        format!("postgres_indexes_{}", self.target.name)
+    }
+
+    async fn collect(&mut self) -> Result<(), MetricCollectionError> {
+        let timestamp = Utc::now();
+
+        // index usage changes slowly, so it is reported on a slower interval
+        if let Some(last_collected) = self.last_collected {
+            if timestamp - last_collected < get_slow_metric_report_interval() {
+                self.metric = None;
+                return Ok(());
+            }
+        }
+
+        // a failed attempt is retried on the next interval
+        self.metric = None;
+        self.metric = Some(*self.collect_metric(timestamp).await?);
+        self.last_collected = Some(timestamp);
+
+        Ok(())
+    }
+
+    async fn save(&self, database: &Database, hostname: &str) -> Result<(), MetricSaveError> {
+        if let Some(metric) = &self.metric {
+            let timestamp = metric.timestamp.clone();
+
+            let futures = metric.clone().stat.into_iter()
+                .map(|entry| save_index_entry(&database, hostname, &timestamp, &metric.target, entry));
+
+            try_join_all(futures).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn encode(&self) -> Result<String, MetricEncodingError> {
+        if let Some(metric) = &self.metric {
+            let v = serde_json::to_string(metric)?;
+            return Ok(v);
+        }
+
+        Err(MetricEncodingError::NoRecord)
+    }
+
+    async fn cleanup(&self, mut database: &Database) -> Result<(), MetricCleanupError> {
+        let min_timestamp = Utc::now() - get_max_metrics_age();
+
+        sqlx::query!("delete from metric_postgres_indexes where timestamp < $1 returning 1 as result", min_timestamp)
+            .fetch_one(&mut database).await?;
+
+        Ok(())
+    }
+}
+
+async fn save_index_entry(mut database: &Database, hostname: &str, timestamp: &DateTime<Utc>, target: &str, entry: IndexMetricEntry) -> Result<(), MetricSaveError> {
+    sqlx::query!(
+        "insert into metric_postgres_indexes (hostname, timestamp, target, schema, table_name, name, scans, size, is_unique, unused, duplicate, invalid) values ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12) returning hostname",
+        hostname.to_string(), *timestamp, target.to_string(), entry.schema, entry.table, entry.index,
+        entry.scans, entry.size, entry.unique, entry.unused, entry.duplicate, entry.invalid
+    ).fetch_one(&mut database).await?;
+
+    Ok(())
+}
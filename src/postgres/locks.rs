@@ -0,0 +1,163 @@
+use chrono::{DateTime, Utc};
+use futures::future::{try_join_all, try_join};
+use futures::TryStreamExt;
+use async_trait::async_trait;
+use serde::Serialize;
+
+use crate::database::Database;
+use crate::config::get_max_metrics_age;
+use crate::postgres::target::PostgresTarget;
+use crate::types::{Metric, MetricCollectionError, MetricSaveError, MetricCleanupError, MetricCollector, MetricEncodingError};
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PostgresLocksMetric {
+    timestamp: DateTime<Utc>,
+    target: String,
+    locks: Vec<LockMetricEntry>,
+    blocking: Vec<BlockingMetricEntry>
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LockMetricEntry {
+    mode: String,
+    granted: bool, // not granted locks are waited for
+    count: i64
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BlockingMetricEntry {
+    database: String,
+    blocked_pid: i32,
+    blocking_pid: i32,
+    duration: f64, // seconds since the blocked query started
+    query: String // of the blocked session
+}
+
+impl Metric for PostgresLocksMetric {
+}
+
+pub struct PostgresLocksMetricCollector {
+    target: PostgresTarget,
+    metric: Option<PostgresLocksMetric>
+}
+
+impl PostgresLocksMetricCollector {
+
+    pub fn new(target: PostgresTarget) -> Self {
+        PostgresLocksMetricCollector {
+            target,
+            metric: None
+        }
+    }
+
+    async fn collect_metric(&self) -> Result<Box<PostgresLocksMetric>, MetricCollectionError> {
+        let database = self.target.database().await?;
+        let mut database = &database;
+
+        let timestamp = Utc::now();
+
+        let locks = sqlx::query!(r"
+SELECT coalesce(mode, '') AS mode, granted, count(*) AS count
+  FROM pg_locks
+  WHERE pid <> pg_backend_pid()
+  GROUP BY 1, 2;").fetch(&mut database).map_ok(|rec| LockMetricEntry {
+            mode: rec.mode,
+            granted: rec.granted,
+            count: rec.count
+        }).try_collect().await?;
+
+        // one row per blocked and blocking session pair, so chains show up as several rows
+        let blocking = sqlx::query!(r"
+SELECT cast(coalesce(a.datname, '') as text) AS database,
+       a.pid AS blocked_pid,
+       b.pid AS blocking_pid,
+       cast(coalesce(extract(epoch from now() - a.query_start), 0) as double precision) AS duration,
+       coalesce(a.query, '') AS query
+  FROM pg_stat_activity a
+  CROSS JOIN LATERAL unnest(pg_blocking_pids(a.pid)) AS b(pid);").fetch(&mut database).map_ok(|rec| BlockingMetricEntry {
+            database: rec.database,
+            blocked_pid: rec.blocked_pid,
+            blocking_pid: rec.blocking_pid,
+            duration: rec.duration,
+            query: rec.query
+        }).try_collect().await?;
+
+        Ok(Box::new(PostgresLocksMetric {
+            timestamp,
+            target: self.target.name.clone(),
+            locks,
+            blocking
+        }))
+    }
+}
+
+#[async_trait]
+impl MetricCollector for PostgresLocksMetricCollector {
+
+    fn key(&self) -> String {
+        format!("postgres_locks_{}", self.target.name)
+    }
+
+    async fn collect(&mut self) -> Result<(), MetricCollectionError> {
+        self.metric = Some(*self.collect_metric().await?);
+
+        Ok(())
+    }
+
+    async fn save(&self, database: &Database, hostname: &str) -> Result<(), MetricSaveError> {
+        if let Some(metric) = &self.metric {
+            let timestamp = metric.timestamp.clone();
+
+            let lock_futures = metric.clone().locks.into_iter()
+                .map(|entry| save_lock_entry(&database, hostname, &timestamp, &metric.target, entry));
+
+            let blocking_futures = metric.clone().blocking.into_iter()
+                .map(|entry| save_blocking_entry(&database, hostname, &timestamp, &metric.target, entry));
+
+            try_join(try_join_all(lock_futures), try_join_all(blocking_futures)).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn encode(&self) -> Result<String, MetricEncodingError> {
+        if let Some(metric) = &self.metric {
+            let v = serde_json::to_string(metric)?;
+            return Ok(v);
+        }
+
+        Err(MetricEncodingError::NoRecord)
+    }
+
+    async fn cleanup(&self, mut database: &Database) -> Result<(), MetricCleanupError> {
+        let min_timestamp = Utc::now() - get_max_metrics_age();
+
+        try_join(
+            sqlx::query!("delete from metric_postgres_locks where timestamp < $1 returning 1 as result", min_timestamp)
+                .fetch_one(&mut database.clone()),
+            sqlx::query!("delete from metric_postgres_blocking where timestamp < $1 returning 1 as result", min_timestamp)
+                .fetch_one(&mut database)
+        ).await?;
+
+        Ok(())
+    }
+}
+
+async fn save_lock_entry(mut database: &Database, hostname: &str, timestamp: &DateTime<Utc>, target: &str, entry: LockMetricEntry) -> Result<(), MetricSaveError> {
+    sqlx::query!(
+        "insert into metric_postgres_locks (hostname, timestamp, target, mode, granted, count) values ($1, $2, $3, $4, $5, $6) returning hostname",
+        hostname.to_string(), *timestamp, target.to_string(), entry.mode, entry.granted, entry.count as i32
+    ).fetch_one(&mut database).await?;
+
+    Ok(())
+}
+
+async fn save_blocking_entry(mut database: &Database, hostname: &str, timestamp: &DateTime<Utc>, target: &str, entry: BlockingMetricEntry) -> Result<(), MetricSaveError> {
+    sqlx::query!(
+        "insert into metric_postgres_blocking (hostname, timestamp, target, database, blocked_pid, blocking_pid, duration, query) values ($1, $2, $3, $4, $5, $6, $7, $8) returning hostname",
+        hostname.to_string(), *timestamp, target.to_string(), entry.database, entry.blocked_pid, entry.blocking_pid,
+        entry.duration, entry.query
+    ).fetch_one(&mut database).await?;
+
+    Ok(())
+}
@@ -0,0 +1,411 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc, Duration};
+use futures::future::{try_join_all, try_join};
+use futures::{TryFutureExt, TryStreamExt};
+use custom_error::custom_error;
+use async_trait::async_trait;
+use serde::Serialize;
+
+use crate::database::Database;
+use crate::config::get_max_metrics_age;
+use crate::postgres::target::PostgresTarget;
+use crate::types::{Metric, MetricCollectionError, MetricSaveError, MetricCleanupError, MetricCollector, MetricEncodingError};
+use sqlx::{PgConnection, Pool};
+
+#[derive(Debug, Clone)]
+pub struct InstantPostgresMetric {
+    timestamp: DateTime<Utc>,
+    database_stat: Vec<DatabaseStat>,
+    table_stat: Vec<TableStat>
+}
+
+#[derive(Debug, Clone)]
+pub struct DatabaseStat {
+    database: String,
+
+    xact_commit: i64,
+    xact_rollback: i64,
+
+    blks_read: i64,
+    blks_hit: i64,
+
+    tup_returned: i64,
+    tup_fetched: i64,
+    tup_inserted: i64,
+    tup_updated: i64,
+    tup_deleted: i64,
+
+    conflicts: i64,
+    temp_files: i64,
+    temp_bytes: i64,
+    deadlocks: i64,
+
+    blk_read_time: f64, // milliseconds
+    blk_write_time: f64
+}
+
+#[derive(Debug, Clone)]
+pub struct TableStat {
+    schema: String,
+    table: String,
+    rows: f32, // estimate
+
+    seq_scan: i64,
+    seq_tup_read: i64,
+    idx_scan: i64,
+
+    live_tuples: i64,
+    dead_tuples: i64,
+
+    // seconds, none if it never happened
+    last_vacuum_age: Option<f64>,
+    last_autovacuum_age: Option<f64>,
+    last_analyze_age: Option<f64>,
+    last_autoanalyze_age: Option<f64>,
+
+    table_bytes: i64,
+    index_bytes: i64,
+    toast_bytes: i64,
+    total_bytes: i64
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PostgresMetric {
+    timestamp: DateTime<Utc>,
+    target: String,
+    database_metrics: Vec<DatabaseMetric>,
+    table_metrics: Vec<TableMetric>
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DatabaseMetric {
+    database: String,
+
+    // all rates are per second
+    xact_commit: f64,
+    xact_rollback: f64,
+
+    blks_read: f64,
+    blks_hit: f64,
+    cache_hit_ratio: Option<f64>, // none when no blocks were requested
+
+    tup_returned: f64,
+    tup_fetched: f64,
+    tup_inserted: f64,
+    tup_updated: f64,
+    tup_deleted: f64,
+
+    conflicts: f64,
+    temp_files: f64,
+    temp_bytes: f64,
+    deadlocks: f64,
+
+    blk_read_time: f64, // milliseconds spent per second
+    blk_write_time: f64
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TableMetric {
+    schema: String,
+    table: String,
+    rows: i64,
+
+    seq_scan: f64, // per second
+    seq_tup_read: f64,
+    idx_scan: f64,
+
+    live_tuples: i64,
+    dead_tuples: i64,
+    dead_tuple_ratio: f64,
+
+    last_vacuum_age: Option<f64>,
+    last_autovacuum_age: Option<f64>,
+    last_analyze_age: Option<f64>,
+    last_autoanalyze_age: Option<f64>,
+
+    table_bytes: i64,
+    index_bytes: i64,
+    toast_bytes: i64,
+    total_bytes: i64
+}
+
+#[async_trait]
+impl Metric for InstantPostgresMetric {
+}
+
+pub struct PostgresMetricCollector {
+    target: PostgresTarget,
+    previous: Option<InstantPostgresMetric>,
+    metric: Option<PostgresMetric>
+}
+
+impl PostgresMetricCollector {
+
+    pub fn new(target: PostgresTarget) -> Self {
+        PostgresMetricCollector {
+            target,
+            previous: None,
+            metric: None
+        }
+    }
+
+    async fn collect_metric(&self) -> Result<Box<InstantPostgresMetric>, MetricCollectionError> {
+        let database = self.target.database().await?;
+        let mut database = &database;
+
+        let timestamp = Utc::now();
+
+        // pg_stat_database also has a row for shared objects (without a database), which is skipped by the join
+        let database_stat = sqlx::query!(r"
+SELECT cast(d.datname as text) AS database, s.xact_commit, s.xact_rollback, s.blks_read, s.blks_hit,
+       s.tup_returned, s.tup_fetched, s.tup_inserted, s.tup_updated, s.tup_deleted,
+       s.conflicts, s.temp_files, s.temp_bytes, s.deadlocks, s.blk_read_time, s.blk_write_time
+  FROM pg_stat_database s
+  JOIN pg_database d ON d.oid = s.datid
+  WHERE NOT d.datistemplate;").fetch(&mut database).map_ok(|rec| DatabaseStat {
+            database: rec.database,
+            xact_commit: rec.xact_commit,
+            xact_rollback: rec.xact_rollback,
+            blks_read: rec.blks_read,
+            blks_hit: rec.blks_hit,
+            tup_returned: rec.tup_returned,
+            tup_fetched: rec.tup_fetched,
+            tup_inserted: rec.tup_inserted,
+            tup_updated: rec.tup_updated,
+            tup_deleted: rec.tup_deleted,
+            conflicts: rec.conflicts,
+            temp_files: rec.temp_files,
+            temp_bytes: rec.temp_bytes,
+            deadlocks: rec.deadlocks,
+            blk_read_time: rec.blk_read_time,
+            blk_write_time: rec.blk_write_time
+        }).try_collect().await?;
+
+        // only tables of the database in the connection string are visible, ages are -1 for never vacuumed/analyzed tables
+        let table_stat = sqlx::query!(r"
+SELECT cast(s.schemaname as text) AS schema_name, cast(s.relname as text) AS table_name, c.reltuples AS row_estimate,
+       s.seq_scan, s.seq_tup_read, coalesce(s.idx_scan, 0) AS idx_scan, s.n_live_tup, s.n_dead_tup,
+       cast(coalesce(extract(epoch from now() - s.last_vacuum), -1) as double precision) AS last_vacuum_age,
+       cast(coalesce(extract(epoch from now() - s.last_autovacuum), -1) as double precision) AS last_autovacuum_age,
+       cast(coalesce(extract(epoch from now() - s.last_analyze), -1) as double precision) AS last_analyze_age,
+       cast(coalesce(extract(epoch from now() - s.last_autoanalyze), -1) as double precision) AS last_autoanalyze_age,
+       pg_indexes_size(s.relid) AS index_bytes,
+       coalesce(pg_total_relation_size(nullif(c.reltoastrelid, 0)), 0) AS toast_bytes,
+       pg_total_relation_size(s.relid) AS total_bytes
+  FROM pg_stat_user_tables s
+  JOIN pg_class c ON c.oid = s.relid;").fetch(&mut database).map_ok(|rec| TableStat {
+            schema: rec.schema_name,
+            table: rec.table_name,
+            rows: rec.row_estimate,
+            seq_scan: rec.seq_scan,
+            seq_tup_read: rec.seq_tup_read,
+            idx_scan: rec.idx_scan,
+            live_tuples: rec.n_live_tup,
+            dead_tuples: rec.n_dead_tup,
+            last_vacuum_age: age_from_seconds(rec.last_vacuum_age),
+            last_autovacuum_age: age_from_seconds(rec.last_autovacuum_age),
+            last_analyze_age: age_from_seconds(rec.last_analyze_age),
+            last_autoanalyze_age: age_from_seconds(rec.last_autoanalyze_age),
+            table_bytes: rec.total_bytes - rec.index_bytes - rec.toast_bytes,
+            index_bytes: rec.index_bytes,
+            toast_bytes: rec.toast_bytes,
+            total_bytes: rec.total_bytes
+        }).try_collect().await?;
+
+        Ok(Box::new(InstantPostgresMetric {
+            timestamp,
+            database_stat,
+            table_stat
+        }))
+    }
+}
+
+#[async_trait]
+impl MetricCollector for PostgresMetricCollector {
+
+    fn key(&self) -> String {
+        format!("postgres_{}", self.target.name)
+    }
+
+    async fn collect(&mut self) -> Result<(), MetricCollectionError> {
+        let metric = self.collect_metric().await?;
+        if let Some(prev) = &self.previous {
+            self.metric = Some(postgres_metric_from_stats(&self.target.name, &prev, &metric));
+        }
+        self.previous = Some(*metric);
+
+        Ok(())
+    }
+
+    async fn save(&self, mut database: &Database, hostname: &str) -> Result<(), MetricSaveError> {
+        if let Some(metric) = &self.metric {
+            let timestamp = metric.timestamp.clone();
+
+            let futures = metric.clone().table_metrics.into_iter()
+                .map(|entry| save_table_metric_entry(&database, hostname, &timestamp, &metric.target, entry));
+
+            let database_futures = metric.clone().database_metrics.into_iter()
+                .map(|entry| save_database_metric(&database, hostname, &timestamp, &metric.target, entry));
+
+            try_join(try_join_all(futures), try_join_all(database_futures)).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn encode(&self) -> Result<String, MetricEncodingError> {
+        if let Some(metric) = &self.metric {
+            let v = serde_json::to_string(metric)?;
+            return Ok(v);
+        }
+
+        Err(MetricEncodingError::NoRecord)
+    }
+
+    async fn cleanup(&self, mut database: &Database) -> Result<(), MetricCleanupError> {
+        let min_timestamp = Utc::now() - get_max_metrics_age();
+
+        try_join(
+            sqlx::query!("delete from metric_postgres_tables where timestamp < $1 returning 1 as result", min_timestamp)
+                .fetch_one(&mut database.clone()),
+            sqlx::query!("delete from metric_postgres_database where timestamp < $1 returning 1 as result", min_timestamp)
+                .fetch_one(&mut database)
+        ).await?;
+
+        Ok(())
+    }
+}
+
+custom_error!{pub PostgresMetricError
+    DatabaseQueryFailed{source: sqlx::error::Error} = "database query failed"
+}
+
+pub fn postgres_metric_from_stats(target: &str, first: &InstantPostgresMetric, second: &InstantPostgresMetric) -> PostgresMetric {
+    let time_diff = second.timestamp - first.timestamp;
+
+    let first_databases: HashMap<&String, &DatabaseStat> = first.database_stat.iter()
+        .map(|v| (&v.database, v))
+        .collect();
+
+    let first_tables: HashMap<(&String, &String), &TableStat> = first.table_stat.iter()
+        .map(|v| ((&v.schema, &v.table), v))
+        .collect();
+
+    PostgresMetric {
+        timestamp: second.timestamp,
+        target: target.to_string(),
+        table_metrics: second.table_stat.iter()
+            .filter_map(|v| first_tables.get(&(&v.schema, &v.table))
+                .map(|item| table_metric_from_two_stats(time_diff, item, v))
+            )
+            .collect(),
+        // databases created since the previous sample are reported starting from the next one
+        database_metrics: second.database_stat.iter()
+            .filter_map(|v| first_databases.get(&v.database)
+                .map(|item| database_metric_from_two_stats(time_diff, item, v))
+            )
+            .collect()
+    }
+}
+
+fn database_metric_from_two_stats(time_diff: Duration, first: &DatabaseStat, second: &DatabaseStat) -> DatabaseMetric {
+    let diff = time_diff.num_milliseconds() as f64 / 1000.0; // seconds
+
+    let blks_read = counter_delta(first.blks_read, second.blks_read);
+    let blks_hit = counter_delta(first.blks_hit, second.blks_hit);
+
+    DatabaseMetric {
+        database: second.database.clone(),
+
+        xact_commit: counter_delta(first.xact_commit, second.xact_commit) as f64 / diff,
+        xact_rollback: counter_delta(first.xact_rollback, second.xact_rollback) as f64 / diff,
+
+        blks_read: blks_read as f64 / diff,
+        blks_hit: blks_hit as f64 / diff,
+        cache_hit_ratio: if blks_read + blks_hit > 0 {
+            Some(blks_hit as f64 / (blks_read + blks_hit) as f64)
+        } else {
+            None
+        },
+
+        tup_returned: counter_delta(first.tup_returned, second.tup_returned) as f64 / diff,
+        tup_fetched: counter_delta(first.tup_fetched, second.tup_fetched) as f64 / diff,
+        tup_inserted: counter_delta(first.tup_inserted, second.tup_inserted) as f64 / diff,
+        tup_updated: counter_delta(first.tup_updated, second.tup_updated) as f64 / diff,
+        tup_deleted: counter_delta(first.tup_deleted, second.tup_deleted) as f64 / diff,
+
+        conflicts: counter_delta(first.conflicts, second.conflicts) as f64 / diff,
+        temp_files: counter_delta(first.temp_files, second.temp_files) as f64 / diff,
+        temp_bytes: counter_delta(first.temp_bytes, second.temp_bytes) as f64 / diff,
+        deadlocks: counter_delta(first.deadlocks, second.deadlocks) as f64 / diff,
+
+        blk_read_time: (second.blk_read_time - first.blk_read_time).max(0.0) / diff,
+        blk_write_time: (second.blk_write_time - first.blk_write_time).max(0.0) / diff
+    }
+}
+
+fn table_metric_from_two_stats(time_diff: Duration, first: &TableStat, second: &TableStat) -> TableMetric {
+    let diff = time_diff.num_milliseconds() as f64 / 1000.0; // seconds
+
+    TableMetric {
+        schema: second.schema.clone(),
+        table: second.table.clone(),
+        rows: second.rows as i64,
+
+        seq_scan: counter_delta(first.seq_scan, second.seq_scan) as f64 / diff,
+        seq_tup_read: counter_delta(first.seq_tup_read, second.seq_tup_read) as f64 / diff,
+        idx_scan: counter_delta(first.idx_scan, second.idx_scan) as f64 / diff,
+
+        live_tuples: second.live_tuples,
+        dead_tuples: second.dead_tuples,
+        dead_tuple_ratio: if second.live_tuples + second.dead_tuples > 0 {
+            second.dead_tuples as f64 / (second.live_tuples + second.dead_tuples) as f64
+        } else {
+            0.0
+        },
+
+        last_vacuum_age: second.last_vacuum_age,
+        last_autovacuum_age: second.last_autovacuum_age,
+        last_analyze_age: second.last_analyze_age,
+        last_autoanalyze_age: second.last_autoanalyze_age,
+
+        table_bytes: second.table_bytes,
+        index_bytes: second.index_bytes,
+        toast_bytes: second.toast_bytes,
+        total_bytes: second.total_bytes
+    }
+}
+
+fn age_from_seconds(age: f64) -> Option<f64> {
+    if age >= 0.0 { Some(age) } else { None }
+}
+
+// statistics counters go back to zero when they are reset with pg_stat_reset()
+pub fn counter_delta(first: i64, second: i64) -> i64 {
+    (second - first).max(0)
+}
+
+async fn save_table_metric_entry(mut database: &Database, hostname: &str, timestamp: &DateTime<Utc>, target: &str, entry: TableMetric) -> Result<(), MetricSaveError> {
+    sqlx::query!(
+        "insert into metric_postgres_tables (hostname, timestamp, target, schema, name, rows, seq_scan, seq_tup_read, idx_scan, live_tuples, dead_tuples, dead_tuple_ratio, last_vacuum_age, last_autovacuum_age, last_analyze_age, last_autoanalyze_age, table_bytes, index_bytes, toast_bytes, total_bytes) values ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18, $19, $20) returning hostname",
+        hostname.to_string(), *timestamp, target.to_string(), entry.schema, entry.table, entry.rows,
+        entry.seq_scan, entry.seq_tup_read, entry.idx_scan, entry.live_tuples, entry.dead_tuples, entry.dead_tuple_ratio,
+        entry.last_vacuum_age, entry.last_autovacuum_age, entry.last_analyze_age, entry.last_autoanalyze_age,
+        entry.table_bytes, entry.index_bytes, entry.toast_bytes, entry.total_bytes
+    ).fetch_one(&mut database).await?;
+
+    Ok(())
+}
+
+async fn save_database_metric(mut database: &Database, hostname: &str, timestamp: &DateTime<Utc>, target: &str, entry: DatabaseMetric) -> Result<(), MetricSaveError> {
+    sqlx::query!(
+        "insert into metric_postgres_database (hostname, timestamp, target, database, xact_commit, xact_rollback, blks_read, blks_hit, cache_hit_ratio, returned, fetched, inserted, updated, deleted, conflicts, temp_files, temp_bytes, deadlocks, blk_read_time, blk_write_time) values ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18, $19, $20) returning hostname",
+        hostname.to_string(), *timestamp, target.to_string(), entry.database, entry.xact_commit, entry.xact_rollback,
+        entry.blks_read, entry.blks_hit, entry.cache_hit_ratio,
+        entry.tup_returned, entry.tup_fetched, entry.tup_inserted, entry.tup_updated, entry.tup_deleted,
+        entry.conflicts, entry.temp_files, entry.temp_bytes, entry.deadlocks, entry.blk_read_time, entry.blk_write_time
+    ).fetch_one(&mut database).await?;
+
+    Ok(())
+}
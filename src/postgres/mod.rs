@@ -0,0 +1,8 @@
+pub mod activity;
+pub mod bgwriter;
+pub mod indexes;
+pub mod locks;
+pub mod metric;
+pub mod replication;
+pub mod statements;
+pub mod target;
@@ -0,0 +1,267 @@
+use chrono::{DateTime, Utc};
+use futures::future::{try_join_all, try_join};
+use futures::TryStreamExt;
+use async_trait::async_trait;
+use serde::Serialize;
+
+use crate::database::Database;
+use crate::config::get_max_metrics_age;
+use crate::postgres::target::PostgresTarget;
+use crate::types::{Metric, MetricCollectionError, MetricSaveError, MetricCleanupError, MetricCollector, MetricEncodingError};
+
+#[derive(Debug, Clone)]
+pub struct InstantWalPosition {
+    timestamp: DateTime<Utc>,
+    position: i64 // bytes since 0/0
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PostgresReplicationMetric {
+    timestamp: DateTime<Utc>,
+    target: String,
+    in_recovery: bool,
+
+    // primary
+    wal_rate: Option<f64>, // bytes per second
+    replicas: Vec<ReplicaMetricEntry>,
+    slots: Vec<ReplicationSlotMetricEntry>,
+
+    // standby
+    replay_lag: Option<f64>, // seconds since the last replayed transaction, zero when everything received is replayed
+    replay_lag_bytes: Option<i64>, // received but not replayed yet
+    receiver_status: Option<String> // none when wal receiver is not running
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ReplicaMetricEntry {
+    application_name: String,
+    client_addr: String,
+    state: String,
+    sync_state: String,
+
+    write_lag_bytes: i64,
+    flush_lag_bytes: i64,
+    replay_lag_bytes: i64,
+
+    write_lag: f64, // seconds
+    flush_lag: f64,
+    replay_lag: f64
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ReplicationSlotMetricEntry {
+    slot_name: String,
+    slot_type: String,
+    active: bool,
+    retained_wal: i64 // bytes
+}
+
+impl Metric for PostgresReplicationMetric {
+}
+
+pub struct PostgresReplicationMetricCollector {
+    target: PostgresTarget,
+    previous: Option<InstantWalPosition>,
+    metric: Option<PostgresReplicationMetric>
+}
+
+impl PostgresReplicationMetricCollector {
+
+    pub fn new(target: PostgresTarget) -> Self {
+        PostgresReplicationMetricCollector {
+            target,
+            previous: None,
+            metric: None
+        }
+    }
+
+    async fn collect_primary(&mut self, mut database: &Database, timestamp: DateTime<Utc>) -> Result<PostgresReplicationMetric, MetricCollectionError> {
+        let position = sqlx::query!("SELECT cast(pg_wal_lsn_diff(pg_current_wal_lsn(), '0/0') as bigint) AS position")
+            .fetch_one(&mut database).await?
+            .position;
+
+        let wal_position = InstantWalPosition { timestamp, position };
+        let wal_rate = self.previous.as_ref().map(|prev| wal_rate_from_two_positions(prev, &wal_position));
+        self.previous = Some(wal_position);
+
+        // time lags are null when a standby has caught up and there is nothing to replicate
+        let replicas = sqlx::query!(r"
+SELECT coalesce(application_name, '') AS application_name,
+       coalesce(cast(client_addr as text), '') AS client_addr,
+       coalesce(state, '') AS state,
+       coalesce(sync_state, '') AS sync_state,
+       cast(coalesce(pg_wal_lsn_diff(pg_current_wal_lsn(), write_lsn), 0) as bigint) AS write_lag_bytes,
+       cast(coalesce(pg_wal_lsn_diff(pg_current_wal_lsn(), flush_lsn), 0) as bigint) AS flush_lag_bytes,
+       cast(coalesce(pg_wal_lsn_diff(pg_current_wal_lsn(), replay_lsn), 0) as bigint) AS replay_lag_bytes,
+       cast(coalesce(extract(epoch from write_lag), 0) as double precision) AS write_lag,
+       cast(coalesce(extract(epoch from flush_lag), 0) as double precision) AS flush_lag,
+       cast(coalesce(extract(epoch from replay_lag), 0) as double precision) AS replay_lag
+  FROM pg_stat_replication;").fetch(&mut database).map_ok(|rec| ReplicaMetricEntry {
+            application_name: rec.application_name,
+            client_addr: rec.client_addr,
+            state: rec.state,
+            sync_state: rec.sync_state,
+            write_lag_bytes: rec.write_lag_bytes,
+            flush_lag_bytes: rec.flush_lag_bytes,
+            replay_lag_bytes: rec.replay_lag_bytes,
+            write_lag: rec.write_lag,
+            flush_lag: rec.flush_lag,
+            replay_lag: rec.replay_lag
+        }).try_collect().await?;
+
+        // an inactive slot keeps wal from being removed until the disk is full
+        let slots = sqlx::query!(r"
+SELECT cast(slot_name as text) AS slot_name,
+       slot_type,
+       active,
+       cast(coalesce(pg_wal_lsn_diff(pg_current_wal_lsn(), restart_lsn), 0) as bigint) AS retained_wal
+  FROM pg_replication_slots;").fetch(&mut database).map_ok(|rec| ReplicationSlotMetricEntry {
+            slot_name: rec.slot_name,
+            slot_type: rec.slot_type,
+            active: rec.active,
+            retained_wal: rec.retained_wal
+        }).try_collect().await?;
+
+        Ok(PostgresReplicationMetric {
+            timestamp,
+            target: self.target.name.clone(),
+            in_recovery: false,
+
+            wal_rate,
+            replicas,
+            slots,
+
+            replay_lag: None,
+            replay_lag_bytes: None,
+            receiver_status: None
+        })
+    }
+
+    async fn collect_standby(&mut self, mut database: &Database, timestamp: DateTime<Utc>) -> Result<PostgresReplicationMetric, MetricCollectionError> {
+        // wal rate is only known on the primary, and the position may go backwards after a failover
+        self.previous = None;
+
+        let lag = sqlx::query!(r"
+SELECT cast(CASE
+         WHEN pg_last_wal_receive_lsn() = pg_last_wal_replay_lsn() THEN 0
+         ELSE coalesce(extract(epoch from now() - pg_last_xact_replay_timestamp()), 0)
+       END as double precision) AS replay_lag,
+       cast(coalesce(pg_wal_lsn_diff(pg_last_wal_receive_lsn(), pg_last_wal_replay_lsn()), 0) as bigint) AS replay_lag_bytes;")
+            .fetch_one(&mut database).await?;
+
+        let receiver_status = sqlx::query!("SELECT status FROM pg_stat_wal_receiver")
+            .fetch_optional(&mut database).await?
+            .map(|rec| rec.status);
+
+        Ok(PostgresReplicationMetric {
+            timestamp,
+            target: self.target.name.clone(),
+            in_recovery: true,
+
+            wal_rate: None,
+            replicas: Vec::new(),
+            slots: Vec::new(),
+
+            replay_lag: Some(lag.replay_lag),
+            replay_lag_bytes: Some(lag.replay_lag_bytes),
+            receiver_status
+        })
+    }
+}
+
+#[async_trait]
+impl MetricCollector for PostgresReplicationMetricCollector {
+
+    fn key(&self) -> String {
+        format!("postgres_replication_{}", self.target.name)
+    }
+
+    async fn collect(&mut self) -> Result<(), MetricCollectionError> {
+        let database = self.target.database().await?;
+        let mut connection = &database;
+
+        let timestamp = Utc::now();
+        let in_recovery = sqlx::query!("SELECT pg_is_in_recovery() AS in_recovery")
+            .fetch_one(&mut connection).await?
+            .in_recovery;
+
+        self.metric = Some(if in_recovery {
+            self.collect_standby(&database, timestamp).await?
+        } else {
+            self.collect_primary(&database, timestamp).await?
+        });
+
+        Ok(())
+    }
+
+    async fn save(&self, mut database: &Database, hostname: &str) -> Result<(), MetricSaveError> {
+        if let Some(metric) = &self.metric {
+            let timestamp = metric.timestamp.clone();
+
+            sqlx::query!(
+                "insert into metric_postgres_replication (hostname, timestamp, target, in_recovery, wal_rate, replay_lag, replay_lag_bytes, receiver_status) values ($1, $2, $3, $4, $5, $6, $7, $8) returning hostname",
+                hostname.to_string(), timestamp, metric.target.clone(), metric.in_recovery, metric.wal_rate,
+                metric.replay_lag, metric.replay_lag_bytes, metric.receiver_status.clone()
+            ).fetch_one(&mut database).await?;
+
+            let replica_futures = metric.clone().replicas.into_iter()
+                .map(|entry| save_replica_entry(&database, hostname, &timestamp, &metric.target, entry));
+
+            let slot_futures = metric.clone().slots.into_iter()
+                .map(|entry| save_slot_entry(&database, hostname, &timestamp, &metric.target, entry));
+
+            try_join(try_join_all(replica_futures), try_join_all(slot_futures)).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn encode(&self) -> Result<String, MetricEncodingError> {
+        if let Some(metric) = &self.metric {
+            let v = serde_json::to_string(metric)?;
+            return Ok(v);
+        }
+
+        Err(MetricEncodingError::NoRecord)
+    }
+
+    async fn cleanup(&self, mut database: &Database) -> Result<(), MetricCleanupError> {
+        let min_timestamp = Utc::now() - get_max_metrics_age();
+
+        try_join(
+            sqlx::query!("delete from metric_postgres_replication where timestamp < $1 returning 1 as result", min_timestamp)
+                .fetch_one(&mut database.clone()),
+            sqlx::query!("delete from metric_postgres_replicas where timestamp < $1 returning 1 as result", min_timestamp)
+                .fetch_one(&mut database.clone())
+        ).await?;
+
+        sqlx::query!("delete from metric_postgres_replication_slots where timestamp < $1 returning 1 as result", min_timestamp)
+            .fetch_one(&mut database).await?;
+
+        Ok(())
+    }
+}
+
+fn wal_rate_from_two_positions(first: &InstantWalPosition, second: &InstantWalPosition) -> f64 {
+    let diff = (second.timestamp - first.timestamp).num_milliseconds() as f64 / 1000.0; // seconds
+    (second.position - first.position).max(0) as f64 / diff
+}
+
+async fn save_replica_entry(mut database: &Database, hostname: &str, timestamp: &DateTime<Utc>, target: &str, entry: ReplicaMetricEntry) -> Result<(), MetricSaveError> {
+    sqlx::query!(
+        "insert into metric_postgres_replicas (hostname, timestamp, target, application_name, client_addr, state, sync_state, write_lag_bytes, flush_lag_bytes, replay_lag_bytes, write_lag, flush_lag, replay_lag) values ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13) returning hostname",
+        hostname.to_string(), *timestamp, target.to_string(), entry.application_name, entry.client_addr, entry.state, entry.sync_state,
+        entry.write_lag_bytes, entry.flush_lag_bytes, entry.replay_lag_bytes, entry.write_lag, entry.flush_lag, entry.replay_lag
+    ).fetch_one(&mut database).await?;
+
+    Ok(())
+}
+
+async fn save_slot_entry(mut database: &Database, hostname: &str, timestamp: &DateTime<Utc>, target: &str, entry: ReplicationSlotMetricEntry) -> Result<(), MetricSaveError> {
+    sqlx::query!(
+        "insert into metric_postgres_replication_slots (hostname, timestamp, target, slot_name, slot_type, active, retained_wal) values ($1, $2, $3, $4, $5, $6, $7) returning hostname",
+        hostname.to_string(), *timestamp, target.to_string(), entry.slot_name, entry.slot_type, entry.active, entry.retained_wal
+    ).fetch_one(&mut database).await?;
+
+    Ok(())
+}
@@ -0,0 +1,259 @@
+use std::env;
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+use futures::future::{try_join_all, try_join};
+use async_trait::async_trait;
+use serde::Serialize;
+use sqlx::Row;
+
+use crate::database::Database;
+use crate::config::get_max_metrics_age;
+use crate::postgres::metric::counter_delta;
+use crate::postgres::target::PostgresTarget;
+use crate::types::{Metric, MetricCollectionError, MetricSaveError, MetricCleanupError, MetricCollector, MetricEncodingError};
+
+const DEFAULT_STATEMENTS_TOP_N: usize = 10;
+
+#[derive(Debug, Clone)]
+pub struct InstantPostgresStatementsMetric {
+    timestamp: DateTime<Utc>,
+    stat: Vec<InstantStatementStat>
+}
+
+#[derive(Debug, Clone)]
+pub struct InstantStatementStat {
+    queryid: i64,
+    database: String,
+    user: String,
+    query: String,
+
+    calls: i64,
+    total_time: f64, // milliseconds
+    rows: i64,
+    shared_blks_read: i64,
+    shared_blks_hit: i64
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PostgresStatementsMetric {
+    timestamp: DateTime<Utc>,
+    target: String,
+    stat: Vec<StatementMetricEntry>
+}
+
+// All values are deltas over the collection interval.
+#[derive(Debug, Clone, Serialize)]
+pub struct StatementMetricEntry {
+    queryid: i64,
+    database: String,
+    user: String,
+    query: String,
+
+    calls: i64,
+    total_time: f64, // milliseconds
+    mean_time: f64,
+    rows: i64,
+    shared_blks_read: i64,
+    shared_blks_hit: i64
+}
+
+impl Metric for InstantPostgresStatementsMetric {
+}
+
+pub struct PostgresStatementsMetricCollector {
+    target: PostgresTarget,
+    previous: Option<InstantPostgresStatementsMetric>,
+    metric: Option<PostgresStatementsMetric>
+}
+
+impl PostgresStatementsMetricCollector {
+
+    pub fn new(target: PostgresTarget) -> Self {
+        PostgresStatementsMetricCollector {
+            target,
+            previous: None,
+            metric: None
+        }
+    }
+
+    async fn collect_metric(&self) -> Result<Box<InstantPostgresStatementsMetric>, MetricCollectionError> {
+        let database = self.target.database().await?;
+        let mut database = &database;
+
+        let installed = sqlx::query!("SELECT count(*) AS installed FROM pg_extension WHERE extname = 'pg_stat_statements'")
+            .fetch_one(&mut database).await?
+            .installed;
+
+        if installed == 0 {
+            return Err(MetricCollectionError::NotConfigured {
+                description: "pg_stat_statements extension is not installed".to_string()
+            });
+        }
+
+        // total_time was split into planning and execution time in postgres 13
+        let version = sqlx::query!("SELECT cast(current_setting('server_version_num') as int) AS version")
+            .fetch_one(&mut database).await?
+            .version;
+        let total_time_column = if version >= 130000 { "total_exec_time" } else { "total_time" };
+
+        let timestamp = Utc::now();
+
+        // the view only exists once the extension is installed, so this query can not be checked at compile time
+        let stat = sqlx::query(&format!(r"
+SELECT s.queryid, cast(d.datname as text) AS database, cast(r.rolname as text) AS user_name, s.query,
+       s.calls, cast(s.{} as double precision) AS total_time, s.rows, s.shared_blks_read, s.shared_blks_hit
+  FROM pg_stat_statements s
+  JOIN pg_database d ON d.oid = s.dbid
+  JOIN pg_roles r ON r.oid = s.userid
+  WHERE s.queryid IS NOT NULL;", total_time_column)).fetch_all(&mut database).await?
+            .into_iter()
+            .map(|row| Ok(InstantStatementStat {
+                queryid: row.try_get("queryid")?,
+                database: row.try_get("database")?,
+                user: row.try_get("user_name")?,
+                query: row.try_get("query")?,
+                calls: row.try_get("calls")?,
+                total_time: row.try_get("total_time")?,
+                rows: row.try_get("rows")?,
+                shared_blks_read: row.try_get("shared_blks_read")?,
+                shared_blks_hit: row.try_get("shared_blks_hit")?
+            }))
+            .collect::<Result<_, sqlx::Error>>()?;
+
+        Ok(Box::new(InstantPostgresStatementsMetric { timestamp, stat }))
+    }
+}
+
+#[async_trait]
+impl MetricCollector for PostgresStatementsMetricCollector {
+
+    fn key(&self) -> String {
+        format!("postgres_statements_{}", self.target.name)
+    }
+
+    async fn collect(&mut self) -> Result<(), MetricCollectionError> {
+        let metric = self.collect_metric().await?;
+        if let Some(prev) = &self.previous {
+            self.metric = Some(statements_metric_from_stats(&self.target.name, prev, &metric));
+        }
+        self.previous = Some(*metric);
+
+        Ok(())
+    }
+
+    async fn save(&self, database: &Database, hostname: &str) -> Result<(), MetricSaveError> {
+        if let Some(metric) = &self.metric {
+            let timestamp = metric.timestamp.clone();
+
+            let futures = metric.clone().stat.into_iter()
+                .map(|entry| save_statement_entry(&database, hostname, &timestamp, &metric.target, entry));
+
+            try_join_all(futures).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn encode(&self) -> Result<String, MetricEncodingError> {
+        if let Some(metric) = &self.metric {
+            let v = serde_json::to_string(metric)?;
+            return Ok(v);
+        }
+
+        Err(MetricEncodingError::NoRecord)
+    }
+
+    async fn cleanup(&self, mut database: &Database) -> Result<(), MetricCleanupError> {
+        let min_timestamp = Utc::now() - get_max_metrics_age();
+
+        try_join(
+            sqlx::query!("delete from metric_postgres_statements where timestamp < $1 returning 1 as result", min_timestamp)
+                .fetch_one(&mut database.clone()),
+            sqlx::query!("delete from postgres_statements where last_seen < $1 returning 1 as result", min_timestamp)
+                .fetch_one(&mut database)
+        ).await?;
+
+        Ok(())
+    }
+}
+
+fn get_statements_top_n() -> usize {
+    env::var("POSTGRES_STATEMENTS_TOP_N").ok()
+        .and_then(|v| v.parse::<usize>().ok())
+        .unwrap_or(DEFAULT_STATEMENTS_TOP_N)
+}
+
+fn statements_metric_from_stats(target: &str, first: &InstantPostgresStatementsMetric, second: &InstantPostgresStatementsMetric) -> PostgresStatementsMetric {
+    let first_entries: HashMap<(i64, &String, &String), &InstantStatementStat> = first.stat.iter()
+        .map(|v| ((v.queryid, &v.database, &v.user), v))
+        .collect();
+
+    // statements which were not called during the interval (or were evicted and came back) are skipped
+    let stat: Vec<StatementMetricEntry> = second.stat.iter()
+        .filter_map(|v| first_entries.get(&(v.queryid, &v.database, &v.user))
+            .map(|item| statement_metric_entry_from_two_stats(item, v))
+        )
+        .filter(|v| v.calls > 0)
+        .collect();
+
+    PostgresStatementsMetric {
+        timestamp: second.timestamp,
+        target: target.to_string(),
+        stat: select_reported_statements(stat)
+    }
+}
+
+fn statement_metric_entry_from_two_stats(first: &InstantStatementStat, second: &InstantStatementStat) -> StatementMetricEntry {
+    let calls = counter_delta(first.calls, second.calls);
+    let total_time = (second.total_time - first.total_time).max(0.0);
+
+    StatementMetricEntry {
+        queryid: second.queryid,
+        database: second.database.clone(),
+        user: second.user.clone(),
+        query: second.query.clone(),
+
+        calls,
+        total_time,
+        mean_time: if calls > 0 { total_time / calls as f64 } else { 0.0 },
+        rows: counter_delta(first.rows, second.rows),
+        shared_blks_read: counter_delta(first.shared_blks_read, second.shared_blks_read),
+        shared_blks_hit: counter_delta(first.shared_blks_hit, second.shared_blks_hit)
+    }
+}
+
+// Only top N statements by total time, by calls and by blocks read from disk are reported.
+fn select_reported_statements(mut stat: Vec<StatementMetricEntry>) -> Vec<StatementMetricEntry> {
+    let top_n = get_statements_top_n();
+    let mut selected: HashMap<(i64, String, String), StatementMetricEntry> = HashMap::new();
+
+    stat.sort_by(|a, b| b.total_time.partial_cmp(&a.total_time).unwrap_or(std::cmp::Ordering::Equal));
+    selected.extend(stat.iter().take(top_n).map(|v| ((v.queryid, v.database.clone(), v.user.clone()), v.clone())));
+
+    stat.sort_by(|a, b| b.calls.cmp(&a.calls));
+    selected.extend(stat.iter().take(top_n).map(|v| ((v.queryid, v.database.clone(), v.user.clone()), v.clone())));
+
+    stat.sort_by(|a, b| b.shared_blks_read.cmp(&a.shared_blks_read));
+    selected.extend(stat.into_iter().take(top_n).map(|v| ((v.queryid, v.database.clone(), v.user.clone()), v)));
+
+    selected.into_iter().map(|v| v.1).collect()
+}
+
+// query text is stored once per queryid instead of with every sample
+async fn save_statement_entry(mut database: &Database, hostname: &str, timestamp: &DateTime<Utc>, target: &str, entry: StatementMetricEntry) -> Result<(), MetricSaveError> {
+    sqlx::query!(
+        r"insert into postgres_statements (target, queryid, query, last_seen) values ($1, $2, $3, $4)
+on conflict (target, queryid) do update set query = excluded.query, last_seen = excluded.last_seen
+returning queryid",
+        target.to_string(), entry.queryid, entry.query, *timestamp
+    ).fetch_one(&mut database).await?;
+
+    sqlx::query!(
+        "insert into metric_postgres_statements (hostname, timestamp, target, queryid, database, user_name, calls, total_time, mean_time, rows, shared_blks_read, shared_blks_hit) values ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12) returning hostname",
+        hostname.to_string(), *timestamp, target.to_string(), entry.queryid, entry.database, entry.user,
+        entry.calls, entry.total_time, entry.mean_time, entry.rows, entry.shared_blks_read, entry.shared_blks_hit
+    ).fetch_one(&mut database).await?;
+
+    Ok(())
+}
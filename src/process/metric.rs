@@ -0,0 +1,237 @@
+use std::env;
+use std::collections::HashMap;
+
+use chrono::{Utc, DateTime, Duration};
+use futures::future::{join_all, try_join_all};
+use async_trait::async_trait;
+use serde::Serialize;
+
+use crate::database::Database;
+use crate::config::get_max_metrics_age;
+use crate::process::procfs::{self, CLOCK_TICKS_PER_SECOND};
+use crate::types::{Metric, MetricCollectionError, MetricSaveError, MetricCleanupError, MetricCollector, MetricEncodingError};
+
+const DEFAULT_PROCESS_TOP_N: usize = 10;
+
+#[derive(Debug, Clone)]
+pub struct InstantProcessMetric {
+    timestamp: DateTime<Utc>,
+    stat: Vec<InstantProcessMetricEntry>
+}
+
+#[derive(Debug, Clone)]
+pub struct InstantProcessMetricEntry {
+    pid: u32,
+    start_time: u64,
+    name: String,
+
+    cpu_time: u64,
+    memory_rss: u64,
+
+    read_bytes: u64,
+    write_bytes: u64,
+
+    threads: u32
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ProcessMetric {
+    timestamp: DateTime<Utc>,
+    stat: Vec<ProcessMetricEntry>
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ProcessMetricEntry {
+    pid: u32,
+    name: String,
+
+    cpu_usage: f64, // percent of a single core
+
+    memory_rss: u64,
+
+    read: f64,
+    write: f64,
+
+    threads: u32
+}
+
+impl Metric for InstantProcessMetric {
+}
+
+pub struct ProcessMetricCollector {
+    previous: Option<InstantProcessMetric>,
+    metric: Option<ProcessMetric>
+}
+
+impl ProcessMetricCollector {
+
+    pub fn new() -> Self {
+        ProcessMetricCollector {
+            previous: None,
+            metric: None
+        }
+    }
+
+    async fn collect_metric(&self) -> Result<Box<InstantProcessMetric>, MetricCollectionError> {
+        let timestamp = Utc::now();
+
+        // processes may exit while we are reading them, so failures for a single process are ignored
+        let stat = join_all(procfs::pids().await?.into_iter().map(collect_process_entry)).await
+            .into_iter()
+            .filter_map(|v| v.ok())
+            .collect();
+
+        Ok(Box::new(InstantProcessMetric { timestamp, stat }))
+    }
+}
+
+#[async_trait]
+impl MetricCollector for ProcessMetricCollector {
+
+    fn key(&self) -> String {
+        "process".to_string()
+    }
+
+    async fn collect(&mut self) -> Result<(), MetricCollectionError> {
+        let metric = self.collect_metric().await?;
+        if let Some(prev) = &self.previous {
+            self.metric = Some(process_metric_from_stats(prev, &metric));
+        }
+        self.previous = Some(*metric);
+
+        Ok(())
+    }
+
+    async fn save(&self, database: &Database, hostname: &str) -> Result<(), MetricSaveError> {
+        if let Some(metric) = &self.metric {
+            let timestamp = metric.timestamp.clone();
+
+            let futures = metric.clone().stat.into_iter()
+                .map(|entry| save_metric_entry(&database, hostname, &timestamp, entry));
+
+            try_join_all(futures).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn encode(&self) -> Result<String, MetricEncodingError> {
+        if let Some(metric) = &self.metric {
+            let v = serde_json::to_string(metric)?;
+            return Ok(v);
+        }
+
+        Err(MetricEncodingError::NoRecord)
+    }
+
+    async fn cleanup(&self, mut database: &Database) -> Result<(), MetricCleanupError> {
+        let min_timestamp = Utc::now() - get_max_metrics_age();
+
+        sqlx::query!("delete from metric_process where timestamp < $1 returning 1 as result", min_timestamp)
+            .fetch_one(&mut database).await?;
+
+        Ok(())
+    }
+}
+
+fn get_process_top_n() -> usize {
+    env::var("PROCESS_TOP_N").ok()
+        .and_then(|v| v.parse::<usize>().ok())
+        .unwrap_or(DEFAULT_PROCESS_TOP_N)
+}
+
+// comma-separated process names (or parts of them) which are always reported
+fn get_process_name_patterns() -> Vec<String> {
+    env::var("PROCESS_NAME_PATTERNS").ok()
+        .map(|v| v.split(',').map(|v| v.trim().to_string()).filter(|v| !v.is_empty()).collect())
+        .unwrap_or_default()
+}
+
+async fn collect_process_entry(pid: u32) -> Result<InstantProcessMetricEntry, MetricCollectionError> {
+    let stat = procfs::stat(pid).await?;
+    let status = procfs::status(pid).await?;
+    let memory_rss = procfs::rss(&status)?;
+    let threads = status.get("Threads")?.parse()?;
+
+    // io is only readable for processes of the same user unless running as root
+    let io = procfs::io(pid).await.unwrap_or_default();
+
+    Ok(InstantProcessMetricEntry {
+        pid,
+        start_time: stat.start_time,
+        name: stat.name,
+
+        cpu_time: stat.cpu_time,
+        memory_rss,
+
+        read_bytes: io.read_bytes,
+        write_bytes: io.write_bytes,
+
+        threads
+    })
+}
+
+fn process_metric_from_stats(first: &InstantProcessMetric, second: &InstantProcessMetric) -> ProcessMetric {
+    let time_diff = second.timestamp - first.timestamp;
+
+    let first_entries: HashMap<(u32, u64), &InstantProcessMetricEntry> = first.stat.iter()
+        .map(|v| ((v.pid, v.start_time), v))
+        .collect();
+
+    let stat: Vec<ProcessMetricEntry> = second.stat.iter()
+        .filter_map(|v| first_entries.get(&(v.pid, v.start_time))
+            .map(|item| process_metric_entry_from_two_stats(time_diff, item, v))
+        )
+        .collect();
+
+    ProcessMetric { stat: select_reported_processes(stat), timestamp: second.timestamp }
+}
+
+fn process_metric_entry_from_two_stats(time_diff: Duration, first: &InstantProcessMetricEntry, second: &InstantProcessMetricEntry) -> ProcessMetricEntry {
+    let diff = time_diff.num_milliseconds() as f64 / 1000.0; // seconds
+
+    let cpu_seconds = second.cpu_time.saturating_sub(first.cpu_time) as f64 / CLOCK_TICKS_PER_SECOND as f64;
+
+    ProcessMetricEntry {
+        pid: second.pid,
+        name: second.name.clone(),
+
+        cpu_usage: cpu_seconds / diff * 100.0,
+
+        memory_rss: second.memory_rss,
+
+        read: second.read_bytes.saturating_sub(first.read_bytes) as f64 / diff,
+        write: second.write_bytes.saturating_sub(first.write_bytes) as f64 / diff,
+
+        threads: second.threads
+    }
+}
+
+// Only top N processes by cpu and by memory are reported, plus all processes matching configured names.
+fn select_reported_processes(mut stat: Vec<ProcessMetricEntry>) -> Vec<ProcessMetricEntry> {
+    let top_n = get_process_top_n();
+    let patterns = get_process_name_patterns();
+
+    let mut selected: HashMap<u32, ProcessMetricEntry> = stat.iter()
+        .filter(|v| patterns.iter().any(|pattern| v.name.contains(pattern)))
+        .map(|v| (v.pid, v.clone()))
+        .collect();
+
+    stat.sort_by(|a, b| b.cpu_usage.partial_cmp(&a.cpu_usage).unwrap_or(std::cmp::Ordering::Equal));
+    selected.extend(stat.iter().take(top_n).map(|v| (v.pid, v.clone())));
+
+    stat.sort_by(|a, b| b.memory_rss.cmp(&a.memory_rss));
+    selected.extend(stat.into_iter().take(top_n).map(|v| (v.pid, v)));
+
+    selected.into_iter().map(|v| v.1).collect()
+}
+
+async fn save_metric_entry(mut database: &Database, hostname: &str, timestamp: &DateTime<Utc>, entry: ProcessMetricEntry) -> Result<(), MetricSaveError> {
+    sqlx::query!(
+        "insert into metric_process (hostname, timestamp, pid, name, cpu_usage, memory_rss, read, write, threads) values ($1, $2, $3, $4, $5, $6, $7, $8, $9) returning hostname",
+        hostname.to_string(), *timestamp, entry.pid as i32, entry.name, entry.cpu_usage, entry.memory_rss as i64,
+        entry.read, entry.write, entry.threads as i32
+    ).fetch_one(&mut database).await?;
+
+    Ok(())
+}
@@ -0,0 +1,3 @@
+pub mod metric;
+pub mod procfs;
+pub mod watch;
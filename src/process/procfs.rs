@@ -0,0 +1,137 @@
+use std::collections::HashMap;
+
+use async_std::fs::{read_to_string, read_dir};
+use chrono::{Utc, DateTime, TimeZone, Duration};
+use futures::StreamExt;
+
+use crate::types::MetricCollectionError;
+
+// USER_HZ, see man 5 proc. It is 100 on all architectures we run on.
+pub const CLOCK_TICKS_PER_SECOND: u64 = 100;
+
+#[derive(Debug, Clone)]
+pub struct ProcessStat {
+    pub pid: u32,
+    pub name: String,
+    pub cpu_time: u64, // utime + stime, in clock ticks
+    pub start_time: u64 // in clock ticks after boot
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ProcessIO {
+    pub read_bytes: u64,
+    pub write_bytes: u64
+}
+
+pub async fn pids() -> Result<Vec<u32>, MetricCollectionError> {
+    let mut entries = read_dir("/proc").await?;
+    let mut pids = Vec::new();
+
+    while let Some(entry) = entries.next().await {
+        if let Some(pid) = entry?.file_name().to_str().and_then(|v| v.parse().ok()) {
+            pids.push(pid);
+        }
+    }
+
+    Ok(pids)
+}
+
+pub async fn stat(pid: u32) -> Result<ProcessStat, MetricCollectionError> {
+    let stat = read_to_string(format!("/proc/{}/stat", pid)).await?;
+
+    // process name is wrapped in parentheses and may contain spaces itself
+    let name_start = stat.find('(')?;
+    let name_end = stat.rfind(')')?;
+    let name = stat[name_start + 1..name_end].to_string();
+
+    // fields after the name start from the third one (state), see man 5 proc
+    let fields: Vec<&str> = stat[name_end + 1..].split_whitespace().collect();
+    let utime: u64 = fields.get(11)?.parse()?;
+    let stime: u64 = fields.get(12)?.parse()?;
+
+    Ok(ProcessStat {
+        pid,
+        name,
+        cpu_time: utime + stime,
+        start_time: fields.get(19)?.parse()?
+    })
+}
+
+// Resident memory in bytes, taken from /proc/[pid]/status (e.g. "VmRSS:  1234 kB") which does not depend on
+// page size, unlike statm. Kernel threads do not have it.
+pub fn rss(status: &HashMap<String, String>) -> Result<u64, MetricCollectionError> {
+    let kilobytes: u64 = match status.get("VmRSS") {
+        Some(v) => v.trim_end_matches("kB").trim().parse()?,
+        None => 0
+    };
+
+    Ok(kilobytes * 1024)
+}
+
+pub async fn io(pid: u32) -> Result<ProcessIO, MetricCollectionError> {
+    let io = key_value_file(&read_to_string(format!("/proc/{}/io", pid)).await?);
+
+    Ok(ProcessIO {
+        read_bytes: io.get("read_bytes")?.parse()?,
+        write_bytes: io.get("write_bytes")?.parse()?
+    })
+}
+
+pub async fn status(pid: u32) -> Result<HashMap<String, String>, MetricCollectionError> {
+    Ok(key_value_file(&read_to_string(format!("/proc/{}/status", pid)).await?))
+}
+
+pub async fn cmdline(pid: u32) -> Result<String, MetricCollectionError> {
+    let cmdline = read_to_string(format!("/proc/{}/cmdline", pid)).await?;
+    Ok(cmdline.trim_end_matches('\0').replace('\0', " "))
+}
+
+pub async fn open_fds(pid: u32) -> Result<u32, MetricCollectionError> {
+    let mut entries = read_dir(format!("/proc/{}/fd", pid)).await?;
+    let mut count = 0;
+
+    while let Some(entry) = entries.next().await {
+        entry?;
+        count += 1;
+    }
+
+    Ok(count)
+}
+
+// Soft RLIMIT_NOFILE of the process, None if unlimited.
+pub async fn max_open_files(pid: u32) -> Result<Option<u64>, MetricCollectionError> {
+    let limits = read_to_string(format!("/proc/{}/limits", pid)).await?;
+    let soft_limit = limits.lines()
+        .find(|line| line.starts_with("Max open files"))?
+        ["Max open files".len()..]
+        .split_whitespace().next()?;
+
+    if soft_limit == "unlimited" {
+        return Ok(None);
+    }
+
+    Ok(Some(soft_limit.parse()?))
+}
+
+pub async fn boot_time() -> Result<DateTime<Utc>, MetricCollectionError> {
+    let stat = read_to_string("/proc/stat").await?;
+    let btime: i64 = stat.lines()
+        .find(|line| line.starts_with("btime "))?
+        .split_whitespace().nth(1)?
+        .parse()?;
+
+    Ok(Utc.timestamp(btime, 0))
+}
+
+pub fn process_start_time(boot_time: DateTime<Utc>, start_time: u64) -> DateTime<Utc> {
+    boot_time + Duration::milliseconds((start_time * 1000 / CLOCK_TICKS_PER_SECOND) as i64)
+}
+
+fn key_value_file(content: &str) -> HashMap<String, String> {
+    content.lines()
+        .filter_map(|line| {
+            let mut spl = line.splitn(2, ':');
+            Some((spl.next()?.trim().to_string(), spl.next()?.trim().to_string()))
+        })
+        .collect()
+}
@@ -0,0 +1,306 @@
+use std::env;
+use std::collections::HashMap;
+
+use async_std::fs::read_to_string;
+use chrono::{Utc, DateTime};
+use futures::future::{join_all, try_join_all, try_join};
+use async_trait::async_trait;
+use log::warn;
+use regex::Regex;
+use serde::Serialize;
+
+use crate::database::Database;
+use crate::config::get_max_metrics_age;
+use crate::process::procfs::{self, ProcessStat, process_start_time};
+use crate::types::{Metric, MetricCollectionError, MetricSaveError, MetricCleanupError, MetricCollector, MetricEncodingError};
+
+// kernel keeps only this many bytes of a process name (TASK_COMM_LEN without the trailing zero)
+const MAX_PROCESS_NAME_LENGTH: usize = 15;
+
+#[derive(Debug, Clone)]
+pub enum ProcessMatcher {
+    Name(String),
+    Cmdline(Regex),
+    Pidfile(String)
+}
+
+#[derive(Debug, Clone)]
+pub struct WatchedProcess {
+    pub name: String,
+    pub matcher: ProcessMatcher
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ProcessWatchMetric {
+    timestamp: DateTime<Utc>,
+    stat: Vec<ProcessWatchMetricEntry>,
+    restarts: Vec<ProcessRestartEntry>
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ProcessWatchMetricEntry {
+    name: String,
+    running: bool,
+    instances: u32,
+    pid: Option<u32>,
+    start_time: Option<DateTime<Utc>>
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ProcessRestartEntry {
+    name: String,
+    previous_pid: Option<u32>,
+    previous_start_time: Option<DateTime<Utc>>,
+    pid: u32,
+    start_time: DateTime<Utc>
+}
+
+impl Metric for ProcessWatchMetric {
+}
+
+pub struct ProcessWatchMetricCollector {
+    watched: Vec<WatchedProcess>,
+    previous: Option<ProcessWatchMetric>,
+    metric: Option<ProcessWatchMetric>
+}
+
+impl ProcessWatchMetricCollector {
+
+    pub fn new() -> Self {
+        ProcessWatchMetricCollector {
+            watched: get_watched_processes(),
+            previous: None,
+            metric: None
+        }
+    }
+
+    async fn collect_metric(&self) -> Result<Box<ProcessWatchMetric>, MetricCollectionError> {
+        if self.watched.is_empty() {
+            return Err(MetricCollectionError::NotConfigured {
+                description: "no processes to watch".to_string()
+            });
+        }
+
+        let timestamp = Utc::now();
+        let boot_time = procfs::boot_time().await?;
+
+        let stat = find_watched_processes(&self.watched).await?.into_iter()
+            .map(|(watched, instances)| {
+                // the oldest instance is considered to be the main process
+                let main = instances.iter().min_by_key(|v| v.start_time);
+
+                ProcessWatchMetricEntry {
+                    name: watched.name.clone(),
+                    running: !instances.is_empty(),
+                    instances: instances.len() as u32,
+                    pid: main.map(|v| v.pid),
+                    start_time: main.map(|v| process_start_time(boot_time, v.start_time))
+                }
+            })
+            .collect();
+
+        Ok(Box::new(ProcessWatchMetric { timestamp, stat, restarts: Vec::new() }))
+    }
+}
+
+#[async_trait]
+impl MetricCollector for ProcessWatchMetricCollector {
+
+    fn key(&self) -> String {
+        "process_watch".to_string()
+    }
+
+    async fn collect(&mut self) -> Result<(), MetricCollectionError> {
+        // restarts are saved once, so nothing is left to save again if collection fails
+        self.metric = None;
+
+        let mut metric = self.collect_metric().await?;
+        if let Some(prev) = &self.previous {
+            metric.restarts = detect_restarts(prev, &metric);
+        }
+        self.previous = Some(*metric.clone());
+        self.metric = Some(*metric);
+
+        Ok(())
+    }
+
+    async fn save(&self, database: &Database, hostname: &str) -> Result<(), MetricSaveError> {
+        if let Some(metric) = &self.metric {
+            let timestamp = metric.timestamp.clone();
+
+            let futures = metric.clone().stat.into_iter()
+                .map(|entry| save_metric_entry(&database, hostname, &timestamp, entry));
+
+            let restart_futures = metric.clone().restarts.into_iter()
+                .map(|entry| save_restart_entry(&database, hostname, &timestamp, entry));
+
+            try_join(try_join_all(futures), try_join_all(restart_futures)).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn encode(&self) -> Result<String, MetricEncodingError> {
+        if let Some(metric) = &self.metric {
+            let v = serde_json::to_string(metric)?;
+            return Ok(v);
+        }
+
+        Err(MetricEncodingError::NoRecord)
+    }
+
+    async fn cleanup(&self, mut database: &Database) -> Result<(), MetricCleanupError> {
+        let min_timestamp = Utc::now() - get_max_metrics_age();
+
+        try_join(
+            sqlx::query!("delete from metric_process_watch where timestamp < $1 returning 1 as result", min_timestamp)
+                .fetch_one(&mut database.clone()),
+            sqlx::query!("delete from metric_process_restarts where timestamp < $1 returning 1 as result", min_timestamp)
+                .fetch_one(&mut database)
+        ).await?;
+
+        Ok(())
+    }
+}
+
+// PROCESS_WATCH is a semicolon-separated list of "<name>=<matcher>:<value>" entries, where matcher is one of
+// "name" (exact process name), "cmdline" (regex matched against the command line) or "pidfile" (path to pidfile).
+// Process names are cut by the kernel to 15 bytes, so only the first 15 bytes of a "name" value are compared.
+// For example: "web=name:nginx;api=cmdline:java .*api\.jar;db=pidfile:/var/run/postgresql/main.pid"
+pub fn get_watched_processes() -> Vec<WatchedProcess> {
+    env::var("PROCESS_WATCH").ok()
+        .map(|v| v.split(';')
+            .map(|v| v.trim())
+            .filter(|v| !v.is_empty())
+            .filter_map(|v| match parse_watched_process(v) {
+                Some(watched) => Some(watched),
+                None => {
+                    warn!("invalid process watch entry: {}", v);
+                    None
+                }
+            })
+            .collect()
+        )
+        .unwrap_or_default()
+}
+
+fn parse_watched_process(entry: &str) -> Option<WatchedProcess> {
+    let mut spl = entry.splitn(2, '=');
+    let name = spl.next()?.trim().to_string();
+
+    let mut spl = spl.next()?.splitn(2, ':');
+    let kind = spl.next()?.trim();
+    let value = spl.next()?.trim().to_string();
+
+    let matcher = match kind {
+        "name" => ProcessMatcher::Name(value),
+        "cmdline" => ProcessMatcher::Cmdline(Regex::new(&value).ok()?),
+        "pidfile" => ProcessMatcher::Pidfile(value),
+        _ => return None
+    };
+
+    Some(WatchedProcess { name, matcher })
+}
+
+pub async fn find_watched_processes(watched: &[WatchedProcess]) -> Result<Vec<(&WatchedProcess, Vec<ProcessStat>)>, MetricCollectionError> {
+    let needs_cmdline = watched.iter().any(|v| match v.matcher {
+        ProcessMatcher::Cmdline(_) => true,
+        _ => false
+    });
+
+    // processes may exit while we are reading them, so failures for a single process are ignored
+    let processes: Vec<(ProcessStat, Option<String>)> = join_all(procfs::pids().await?.into_iter()
+        .map(|pid| read_process(pid, needs_cmdline))
+    ).await.into_iter()
+        .filter_map(|v| v.ok())
+        .collect();
+
+    let mut result = Vec::new();
+
+    for watched_process in watched {
+        let instances = match &watched_process.matcher {
+            ProcessMatcher::Name(name) => processes.iter()
+                .filter(|v| v.0.name == truncate_process_name(name))
+                .map(|v| v.0.clone())
+                .collect(),
+            ProcessMatcher::Cmdline(regex) => processes.iter()
+                .filter(|v| v.1.as_ref().map(|cmdline| regex.is_match(cmdline)).unwrap_or(false))
+                .map(|v| v.0.clone())
+                .collect(),
+            ProcessMatcher::Pidfile(path) => {
+                let pid = read_to_string(path).await.ok()
+                    .and_then(|v| v.trim().parse::<u32>().ok());
+
+                processes.iter()
+                    .filter(|v| Some(v.0.pid) == pid)
+                    .map(|v| v.0.clone())
+                    .collect()
+            }
+        };
+
+        result.push((watched_process, instances));
+    }
+
+    Ok(result)
+}
+
+fn truncate_process_name(name: &str) -> &str {
+    let mut end = name.len().min(MAX_PROCESS_NAME_LENGTH);
+    while !name.is_char_boundary(end) {
+        end -= 1;
+    }
+
+    &name[..end]
+}
+
+async fn read_process(pid: u32, with_cmdline: bool) -> Result<(ProcessStat, Option<String>), MetricCollectionError> {
+    let stat = procfs::stat(pid).await?;
+    let cmdline = if with_cmdline { Some(procfs::cmdline(pid).await?) } else { None };
+    Ok((stat, cmdline))
+}
+
+fn detect_restarts(first: &ProcessWatchMetric, second: &ProcessWatchMetric) -> Vec<ProcessRestartEntry> {
+    let first_entries: HashMap<&String, &ProcessWatchMetricEntry> = first.stat.iter()
+        .map(|v| (&v.name, v))
+        .collect();
+
+    second.stat.iter()
+        .filter_map(|v| {
+            let previous = first_entries.get(&v.name)?;
+            let pid = v.pid?;
+            let start_time = v.start_time?;
+
+            if previous.pid == Some(pid) && previous.start_time == Some(start_time) {
+                return None;
+            }
+
+            Some(ProcessRestartEntry {
+                name: v.name.clone(),
+                previous_pid: previous.pid,
+                previous_start_time: previous.start_time,
+                pid,
+                start_time
+            })
+        })
+        .collect()
+}
+
+async fn save_metric_entry(mut database: &Database, hostname: &str, timestamp: &DateTime<Utc>, entry: ProcessWatchMetricEntry) -> Result<(), MetricSaveError> {
+    sqlx::query!(
+        "insert into metric_process_watch (hostname, timestamp, name, running, instances, pid, start_time) values ($1, $2, $3, $4, $5, $6, $7) returning hostname",
+        hostname.to_string(), *timestamp, entry.name, entry.running, entry.instances as i32,
+        entry.pid.map(|v| v as i32), entry.start_time
+    ).fetch_one(&mut database).await?;
+
+    Ok(())
+}
+
+async fn save_restart_entry(mut database: &Database, hostname: &str, timestamp: &DateTime<Utc>, entry: ProcessRestartEntry) -> Result<(), MetricSaveError> {
+    sqlx::query!(
+        "insert into metric_process_restarts (hostname, timestamp, name, previous_pid, previous_start_time, pid, start_time) values ($1, $2, $3, $4, $5, $6, $7) returning hostname",
+        hostname.to_string(), *timestamp, entry.name, entry.previous_pid.map(|v| v as i32), entry.previous_start_time,
+        entry.pid as i32, entry.start_time
+    ).fetch_one(&mut database).await?;
+
+    Ok(())
+}
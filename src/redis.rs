@@ -0,0 +1,389 @@
+use std::env;
+use std::collections::HashMap;
+use std::time::Duration;
+
+use async_std::future::timeout;
+use async_std::io::{self, BufReader, Read, Write};
+use async_std::net::TcpStream;
+use async_std::os::unix::net::UnixStream;
+use async_std::prelude::*;
+use chrono::{Utc, DateTime};
+use futures::future::{try_join_all, try_join};
+use async_trait::async_trait;
+use serde::Serialize;
+
+use crate::database::Database;
+use crate::config::get_max_metrics_age;
+use crate::types::{Metric, MetricCollectionError, MetricSaveError, MetricCleanupError, MetricCollector, MetricEncodingError};
+
+const REDIS_TIMEOUT: u64 = 10; // seconds
+
+#[derive(Debug, Clone)]
+pub struct InstantRedisMetric {
+    timestamp: DateTime<Utc>,
+
+    connected_clients: u64,
+    blocked_clients: u64,
+
+    used_memory: u64,
+    used_memory_peak: u64,
+    used_memory_rss: u64,
+    mem_fragmentation_ratio: f64,
+
+    total_commands_processed: u64,
+    keyspace_hits: u64,
+    keyspace_misses: u64,
+    evicted_keys: u64,
+    expired_keys: u64,
+
+    replication: RedisReplication,
+    keyspace: Vec<RedisKeyspaceEntry>
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RedisReplication {
+    role: String,
+    repl_offset: i64,
+
+    // replica
+    master_link_up: Option<bool>,
+    master_last_io_seconds_ago: Option<i64>,
+
+    // master
+    replicas: Vec<RedisReplicaEntry>
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RedisReplicaEntry {
+    address: String,
+    state: String,
+    offset_lag: i64, // bytes
+    lag: i64 // seconds since the last ack
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RedisKeyspaceEntry {
+    db: String,
+    keys: u64,
+    expires: u64
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RedisMetric {
+    timestamp: DateTime<Utc>,
+
+    connected_clients: u64,
+    blocked_clients: u64,
+
+    used_memory: u64,
+    used_memory_peak: u64,
+    used_memory_rss: u64,
+    mem_fragmentation_ratio: f64,
+
+    // per second
+    ops: f64,
+    keyspace_hits: f64,
+    keyspace_misses: f64,
+    hit_ratio: Option<f64>, // none when there were no lookups
+    evicted_keys: f64,
+    expired_keys: f64,
+
+    replication: RedisReplication,
+    keyspace: Vec<RedisKeyspaceEntry>
+}
+
+impl Metric for InstantRedisMetric {
+}
+
+pub struct RedisMetricCollector {
+    previous: Option<InstantRedisMetric>,
+    metric: Option<RedisMetric>
+}
+
+impl RedisMetricCollector {
+
+    pub fn new() -> Self {
+        RedisMetricCollector {
+            previous: None,
+            metric: None
+        }
+    }
+
+    async fn collect_metric(&self) -> Result<Box<InstantRedisMetric>, MetricCollectionError> {
+        let address = match get_redis_address() {
+            Some(v) => v,
+            None => return Err(MetricCollectionError::NotConfigured { description: "redis not configured".to_string() })
+        };
+
+        let timestamp = Utc::now();
+
+        let info = timeout(Duration::from_secs(REDIS_TIMEOUT), request_info(&address, get_redis_password())).await
+            .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "redis request timed out"))??;
+
+        redis_metric_from_info(timestamp, &parse_info(&info)).map(Box::new)
+    }
+}
+
+#[async_trait]
+impl MetricCollector for RedisMetricCollector {
+
+    fn key(&self) -> String {
+        "redis".to_string()
+    }
+
+    async fn collect(&mut self) -> Result<(), MetricCollectionError> {
+        let metric = self.collect_metric().await?;
+        if let Some(prev) = &self.previous {
+            self.metric = Some(redis_metric_from_stats(prev, &metric));
+        }
+        self.previous = Some(*metric);
+        Ok(())
+    }
+
+    async fn save(&self, mut database: &Database, hostname: &str) -> Result<(), MetricSaveError> {
+        if let Some(metric) = &self.metric {
+            let timestamp = metric.timestamp.clone();
+            let replication = &metric.replication;
+
+            sqlx::query!(
+                "insert into metric_redis (hostname, timestamp, connected_clients, blocked_clients, used_memory, used_memory_peak, used_memory_rss, mem_fragmentation_ratio, ops, keyspace_hits, keyspace_misses, hit_ratio, evicted_keys, expired_keys, role, repl_offset, master_link_up, master_last_io_seconds_ago) values ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18) returning hostname",
+                hostname.to_string(), timestamp, metric.connected_clients as i32, metric.blocked_clients as i32,
+                metric.used_memory as i64, metric.used_memory_peak as i64, metric.used_memory_rss as i64, metric.mem_fragmentation_ratio,
+                metric.ops, metric.keyspace_hits, metric.keyspace_misses, metric.hit_ratio, metric.evicted_keys, metric.expired_keys,
+                replication.role.clone(), replication.repl_offset, replication.master_link_up, replication.master_last_io_seconds_ago
+            ).fetch_one(&mut database).await?;
+
+            let keyspace_futures = metric.clone().keyspace.into_iter()
+                .map(|entry| save_keyspace_entry(&database, hostname, &timestamp, entry));
+
+            let replica_futures = replication.clone().replicas.into_iter()
+                .map(|entry| save_replica_entry(&database, hostname, &timestamp, entry));
+
+            try_join(try_join_all(keyspace_futures), try_join_all(replica_futures)).await?;
+        }
+        Ok(())
+    }
+
+    async fn encode(&self) -> Result<String, MetricEncodingError> {
+        if let Some(metric) = &self.metric {
+            let v = serde_json::to_string(metric)?;
+            return Ok(v);
+        }
+
+        Err(MetricEncodingError::NoRecord)
+    }
+
+    async fn cleanup(&self, mut database: &Database) -> Result<(), MetricCleanupError> {
+        let min_timestamp = Utc::now() - get_max_metrics_age();
+
+        try_join(
+            sqlx::query!("delete from metric_redis where timestamp < $1 returning 1 as result", min_timestamp)
+                .fetch_one(&mut database.clone()),
+            sqlx::query!("delete from metric_redis_keyspace where timestamp < $1 returning 1 as result", min_timestamp)
+                .fetch_one(&mut database.clone())
+        ).await?;
+
+        sqlx::query!("delete from metric_redis_replicas where timestamp < $1 returning 1 as result", min_timestamp)
+            .fetch_one(&mut database).await?;
+
+        Ok(())
+    }
+}
+
+// REDIS_ADDRESS is either "host:port" or a path to the unix socket
+fn get_redis_address() -> Option<String> {
+    env::var("REDIS_ADDRESS").ok()
+}
+
+fn get_redis_password() -> Option<String> {
+    env::var("REDIS_PASSWORD").ok()
+}
+
+async fn request_info(address: &str, password: Option<String>) -> Result<String, MetricCollectionError> {
+    if address.starts_with('/') {
+        request_info_from_stream(UnixStream::connect(address).await?, password).await
+    } else {
+        request_info_from_stream(TcpStream::connect(address).await?, password).await
+    }
+}
+
+async fn request_info_from_stream<S: Read + Write + Unpin>(stream: S, password: Option<String>) -> Result<String, MetricCollectionError> {
+    let mut stream = BufReader::new(stream);
+
+    if let Some(password) = password {
+        send_command(&mut stream, &["AUTH", &password]).await?;
+        read_reply(&mut stream).await?;
+    }
+
+    send_command(&mut stream, &["INFO", "ALL"]).await?;
+    read_reply(&mut stream).await
+}
+
+// Commands are sent as RESP arrays of bulk strings.
+async fn send_command<S: Read + Write + Unpin>(stream: &mut BufReader<S>, args: &[&str]) -> Result<(), MetricCollectionError> {
+    let mut command = format!("*{}\r\n", args.len());
+    for arg in args {
+        command.push_str(&format!("${}\r\n{}\r\n", arg.len(), arg));
+    }
+
+    stream.get_mut().write_all(command.as_bytes()).await?;
+    Ok(())
+}
+
+// Only simple string, error and bulk string replies are expected for AUTH and INFO.
+async fn read_reply<S: Read + Write + Unpin>(stream: &mut BufReader<S>) -> Result<String, MetricCollectionError> {
+    let mut line = String::new();
+    stream.read_line(&mut line).await?;
+    let line = line.trim_end();
+
+    match line.chars().next() {
+        Some('+') => Ok(line[1..].to_string()),
+        Some('-') => Err(MetricCollectionError::FailedToParse {
+            description: format!("redis returned an error: {}", &line[1..])
+        }),
+        Some('$') => {
+            let len: usize = line[1..].parse()?;
+            let mut body = vec![0; len + 2]; // with trailing \r\n
+            stream.read_exact(&mut body).await?;
+            body.truncate(len);
+
+            Ok(String::from_utf8_lossy(&body).to_string())
+        },
+        _ => Err(MetricCollectionError::FailedToParse {
+            description: format!("unexpected redis reply: {}", line)
+        })
+    }
+}
+
+// INFO reply is a list of "key:value" lines split into sections by "# Section" headers
+fn parse_info(info: &str) -> HashMap<String, String> {
+    info.lines()
+        .filter(|v| !v.starts_with('#'))
+        .filter_map(|v| {
+            let mut spl = v.splitn(2, ':');
+            Some((spl.next()?.to_string(), spl.next()?.trim().to_string()))
+        })
+        .collect()
+}
+
+// parses values like "keys=1,expires=0,avg_ttl=0"
+fn parse_info_fields(value: &str) -> HashMap<&str, &str> {
+    value.split(',')
+        .filter_map(|v| {
+            let mut spl = v.splitn(2, '=');
+            Some((spl.next()?, spl.next()?))
+        })
+        .collect()
+}
+
+fn redis_metric_from_info(timestamp: DateTime<Utc>, info: &HashMap<String, String>) -> Result<InstantRedisMetric, MetricCollectionError> {
+    let mut keyspace = Vec::new();
+    for (key, value) in info.iter().filter(|v| v.0.starts_with("db") && v.0[2..].parse::<u32>().is_ok()) {
+        let fields = parse_info_fields(value);
+        keyspace.push(RedisKeyspaceEntry {
+            db: key.clone(),
+            keys: fields.get("keys")?.parse()?,
+            expires: fields.get("expires")?.parse()?
+        });
+    }
+
+    let role = info.get("role")?.clone();
+    let repl_offset: i64 = info.get("master_repl_offset")?.parse()?;
+
+    let mut replicas = Vec::new();
+    for value in info.iter().filter(|v| v.0.starts_with("slave") && v.0[5..].parse::<u32>().is_ok()).map(|v| v.1) {
+        let fields = parse_info_fields(value);
+        let offset: i64 = fields.get("offset")?.parse()?;
+
+        replicas.push(RedisReplicaEntry {
+            address: format!("{}:{}", fields.get("ip").unwrap_or(&""), fields.get("port").unwrap_or(&"")),
+            state: fields.get("state").unwrap_or(&"unknown").to_string(),
+            offset_lag: (repl_offset - offset).max(0),
+            lag: fields.get("lag").and_then(|v| v.parse().ok()).unwrap_or(0)
+        });
+    }
+
+    let is_replica = role == "slave";
+
+    Ok(InstantRedisMetric {
+        timestamp,
+
+        connected_clients: info.get("connected_clients")?.parse()?,
+        blocked_clients: info.get("blocked_clients")?.parse()?,
+
+        used_memory: info.get("used_memory")?.parse()?,
+        used_memory_peak: info.get("used_memory_peak")?.parse()?,
+        used_memory_rss: info.get("used_memory_rss")?.parse()?,
+        mem_fragmentation_ratio: info.get("mem_fragmentation_ratio")?.parse()?,
+
+        total_commands_processed: info.get("total_commands_processed")?.parse()?,
+        keyspace_hits: info.get("keyspace_hits")?.parse()?,
+        keyspace_misses: info.get("keyspace_misses")?.parse()?,
+        evicted_keys: info.get("evicted_keys")?.parse()?,
+        expired_keys: info.get("expired_keys")?.parse()?,
+
+        replication: RedisReplication {
+            role,
+            repl_offset,
+
+            master_link_up: if is_replica { info.get("master_link_status").map(|v| v == "up") } else { None },
+            // -1 when the link is down
+            master_last_io_seconds_ago: if is_replica { info.get("master_last_io_seconds_ago").and_then(|v| v.parse().ok()) } else { None },
+
+            replicas
+        },
+        keyspace
+    })
+}
+
+fn redis_metric_from_stats(first: &InstantRedisMetric, second: &InstantRedisMetric) -> RedisMetric {
+    let diff = (second.timestamp - first.timestamp).num_milliseconds() as f64 / 1000.0; // seconds
+
+    // counters start from zero after a restart
+    let keyspace_hits = second.keyspace_hits.saturating_sub(first.keyspace_hits);
+    let keyspace_misses = second.keyspace_misses.saturating_sub(first.keyspace_misses);
+
+    RedisMetric {
+        timestamp: second.timestamp,
+
+        connected_clients: second.connected_clients,
+        blocked_clients: second.blocked_clients,
+
+        used_memory: second.used_memory,
+        used_memory_peak: second.used_memory_peak,
+        used_memory_rss: second.used_memory_rss,
+        mem_fragmentation_ratio: second.mem_fragmentation_ratio,
+
+        ops: second.total_commands_processed.saturating_sub(first.total_commands_processed) as f64 / diff,
+        keyspace_hits: keyspace_hits as f64 / diff,
+        keyspace_misses: keyspace_misses as f64 / diff,
+        hit_ratio: if keyspace_hits + keyspace_misses > 0 {
+            Some(keyspace_hits as f64 / (keyspace_hits + keyspace_misses) as f64)
+        } else {
+            None
+        },
+        evicted_keys: second.evicted_keys.saturating_sub(first.evicted_keys) as f64 / diff,
+        expired_keys: second.expired_keys.saturating_sub(first.expired_keys) as f64 / diff,
+
+        replication: second.replication.clone(),
+        keyspace: second.keyspace.clone()
+    }
+}
+
+async fn save_keyspace_entry(mut database: &Database, hostname: &str, timestamp: &DateTime<Utc>, entry: RedisKeyspaceEntry) -> Result<(), MetricSaveError> {
+    sqlx::query!(
+        "insert into metric_redis_keyspace (hostname, timestamp, db, keys, expires) values ($1, $2, $3, $4, $5) returning hostname",
+        hostname.to_string(), *timestamp, entry.db, entry.keys as i64, entry.expires as i64
+    ).fetch_one(&mut database).await?;
+
+    Ok(())
+}
+
+async fn save_replica_entry(mut database: &Database, hostname: &str, timestamp: &DateTime<Utc>, entry: RedisReplicaEntry) -> Result<(), MetricSaveError> {
+    sqlx::query!(
+        "insert into metric_redis_replicas (hostname, timestamp, address, state, offset_lag, lag) values ($1, $2, $3, $4, $5, $6) returning hostname",
+        hostname.to_string(), *timestamp, entry.address, entry.state, entry.offset_lag, entry.lag
+    ).fetch_one(&mut database).await?;
+
+    Ok(())
+}
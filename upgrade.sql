@@ -0,0 +1,123 @@
+-- Brings tables created by a previous version of schema.sql up to date. Tables which did not exist before
+-- are not listed here, create them from schema.sql. Rows recorded before the upgrade get zeros in new columns
+-- which are not nullable.
+
+begin;
+
+alter table metric_docker_containers
+    add column if not exists cpu_throttled_periods double precision,
+    add column if not exists cpu_throttled_time double precision,
+    add column if not exists memory_limit bigint,
+    add column if not exists memory_percent double precision,
+    add column if not exists block_read double precision,
+    add column if not exists block_write double precision,
+    add column if not exists block_read_ops double precision,
+    add column if not exists block_write_ops double precision,
+    add column if not exists pids integer;
+
+alter table metric_docker_containers
+    add column if not exists image text,
+    add column if not exists compose_project text,
+    add column if not exists compose_service text,
+    add column if not exists health text,
+    add column if not exists restart_count integer,
+    add column if not exists exit_code integer,
+    add column if not exists created_at timestamp with time zone,
+    add column if not exists started_at timestamp with time zone;
+
+-- stopped containers are recorded without usage stats
+alter table metric_docker_containers
+    alter column cpu_usage drop not null,
+    alter column memory_usage drop not null,
+    alter column memory_cache drop not null,
+    alter column network_tx drop not null,
+    alter column network_rx drop not null;
+
+-- rows recorded before postgres targets were configurable belong to the metrics database itself
+alter table metric_postgres_database
+    add column if not exists target text;
+
+update metric_postgres_database set target = 'local' where target is null;
+
+alter table metric_postgres_tables
+    add column if not exists target text not null default 'local';
+
+alter table metric_postgres_tables
+    alter column target drop default;
+
+-- columns were nullable before, such rows can not be converted to rates and are removed
+delete from metric_postgres_database
+    where hostname is null or timestamp is null
+       or returned is null or fetched is null or inserted is null or updated is null or deleted is null;
+
+alter table metric_postgres_database
+    alter column hostname set not null,
+    alter column timestamp set not null,
+    alter column target set not null,
+    alter column returned type double precision,
+    alter column returned set not null,
+    alter column fetched type double precision,
+    alter column fetched set not null,
+    alter column inserted type double precision,
+    alter column inserted set not null,
+    alter column updated type double precision,
+    alter column updated set not null,
+    alter column deleted type double precision,
+    alter column deleted set not null,
+    add column if not exists database text not null default '',
+    add column if not exists xact_commit double precision not null default 0,
+    add column if not exists xact_rollback double precision not null default 0,
+    add column if not exists blks_read double precision not null default 0,
+    add column if not exists blks_hit double precision not null default 0,
+    add column if not exists cache_hit_ratio double precision,
+    add column if not exists conflicts double precision not null default 0,
+    add column if not exists temp_files double precision not null default 0,
+    add column if not exists temp_bytes double precision not null default 0,
+    add column if not exists deadlocks double precision not null default 0,
+    add column if not exists blk_read_time double precision not null default 0,
+    add column if not exists blk_write_time double precision not null default 0;
+
+alter table metric_postgres_database
+    alter column database drop default,
+    alter column xact_commit drop default,
+    alter column xact_rollback drop default,
+    alter column blks_read drop default,
+    alter column blks_hit drop default,
+    alter column conflicts drop default,
+    alter column temp_files drop default,
+    alter column temp_bytes drop default,
+    alter column deadlocks drop default,
+    alter column blk_read_time drop default,
+    alter column blk_write_time drop default;
+
+-- tables were not schema-qualified before, most of them live in the public schema
+alter table metric_postgres_tables
+    alter column rows type bigint,
+    add column if not exists schema text not null default 'public',
+    add column if not exists seq_scan double precision not null default 0,
+    add column if not exists seq_tup_read double precision not null default 0,
+    add column if not exists idx_scan double precision not null default 0,
+    add column if not exists live_tuples bigint not null default 0,
+    add column if not exists dead_tuples bigint not null default 0,
+    add column if not exists dead_tuple_ratio double precision not null default 0,
+    add column if not exists last_vacuum_age double precision,
+    add column if not exists last_autovacuum_age double precision,
+    add column if not exists last_analyze_age double precision,
+    add column if not exists last_autoanalyze_age double precision,
+    add column if not exists table_bytes bigint not null default 0,
+    add column if not exists index_bytes bigint not null default 0,
+    add column if not exists toast_bytes bigint not null default 0;
+
+alter table metric_postgres_tables
+    alter column schema drop default,
+    alter column seq_scan drop default,
+    alter column seq_tup_read drop default,
+    alter column idx_scan drop default,
+    alter column live_tuples drop default,
+    alter column dead_tuples drop default,
+    alter column dead_tuple_ratio drop default,
+    alter column table_bytes drop default,
+    alter column index_bytes drop default,
+    alter column toast_bytes drop default;
+
+commit;